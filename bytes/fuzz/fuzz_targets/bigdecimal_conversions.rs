@@ -0,0 +1,32 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use bytes::{IntoBigDecimal, TryFromBigDecimal};
+use ethers::types::{H256, U128, U256, U64};
+use libfuzzer_sys::fuzz_target;
+
+/// Any value a conversion accepts must convert back to the same number
+fn check<T: IntoBigDecimal + TryFromBigDecimal>(value: &BigDecimal) {
+    if let Ok(converted) = T::try_from_bigdecimal(value) {
+        assert_eq!(&converted.into_bigdecimal(), value);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // raw bytes must survive a trip through every width that can hold them
+    if data.len() <= 32 {
+        let n = U256::from_little_endian(data);
+        assert_eq!(U256::try_from_bigdecimal(&n.into_bigdecimal()), Ok(n));
+    }
+
+    // decimal strings exercise signs, fractions and exponents
+    let Ok(text) = std::str::from_utf8(data) else { return };
+    let Ok(value) = BigDecimal::from_str(text) else { return };
+    check::<U64>(&value);
+    check::<U128>(&value);
+    check::<U256>(&value);
+    check::<H256>(&value);
+    check::<i64>(&value);
+});
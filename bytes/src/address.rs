@@ -0,0 +1,185 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ethers::types::H160;
+use ethers::utils::to_checksum;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+
+/// An Ethereum address
+///
+/// Stored as lowercase `0x`-prefixed hex, which is the form the database `address` domain
+/// accepts, and displayed with its EIP-55 checksum.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(H160);
+
+/// Returned when a string is not a valid Ethereum address
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseAddressError {
+    /// The address is not 40 hex characters long
+    InvalidLength,
+    /// The address contains characters that are not hex digits
+    InvalidHex,
+    /// The address is mixed case but does not match its EIP-55 checksum
+    InvalidChecksum,
+}
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength => write!(f, "address must be 40 hex characters"),
+            Self::InvalidHex => write!(f, "address must only contain hex characters"),
+            Self::InvalidChecksum => write!(f, "address does not match its checksum"),
+        }
+    }
+}
+
+impl std::error::Error for ParseAddressError {}
+
+impl Address {
+    /// Returns the lowercase `0x`-prefixed form used for storage
+    pub fn to_lowercase_hex(&self) -> String {
+        format!("{:#x}", self.0)
+    }
+
+    /// Returns the EIP-55 checksummed form used for display
+    pub fn to_checksum(&self) -> String {
+        to_checksum(&self.0, None)
+    }
+}
+
+impl From<H160> for Address {
+    fn from(address: H160) -> Self {
+        Self(address)
+    }
+}
+
+impl From<Address> for H160 {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    /// Parses a hex address with or without the `0x` prefix, checking the EIP-55 checksum when
+    /// the address is mixed case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.len() != 40 {
+            return Err(ParseAddressError::InvalidLength);
+        }
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseAddressError::InvalidHex);
+        }
+
+        let address = Self(hex.parse().map_err(|_| ParseAddressError::InvalidHex)?);
+
+        let is_mixed_case = hex.bytes().any(|b| b.is_ascii_lowercase())
+            && hex.bytes().any(|b| b.is_ascii_uppercase());
+        if is_mixed_case && address.to_checksum()[2..] != *hex {
+            return Err(ParseAddressError::InvalidChecksum);
+        }
+
+        Ok(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_checksum())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // owned, since buffered input such as a `serde_json::Value` cannot lend a `&str`
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Type<Postgres> for Address {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for Address {
+    fn array_type_info() -> PgTypeInfo {
+        <String as PgHasArrayType>::array_type_info()
+    }
+
+    fn array_compatible(ty: &PgTypeInfo) -> bool {
+        <String as PgHasArrayType>::array_compatible(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for Address {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <String as Encode<Postgres>>::encode(self.to_lowercase_hex(), buf)
+    }
+}
+
+impl Decode<'_, Postgres> for Address {
+    fn decode(value: PgValueRef<'_>) -> Result<Self, BoxDynError> {
+        let address = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(address.trim_end().parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    #[test]
+    fn parses_with_or_without_checksum() {
+        let address: Address = CHECKSUMMED.parse().unwrap();
+        assert_eq!(address.to_string(), CHECKSUMMED);
+        assert_eq!(
+            address.to_lowercase_hex(),
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+        );
+        assert_eq!(Ok(address), CHECKSUMMED.to_lowercase().parse());
+        assert_eq!(Ok(address), CHECKSUMMED[2..].parse());
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        let bad_checksum = CHECKSUMMED.replace('a', "A");
+        assert_eq!(
+            bad_checksum.parse::<Address>(),
+            Err(ParseAddressError::InvalidChecksum)
+        );
+        assert_eq!(
+            "0x1234".parse::<Address>(),
+            Err(ParseAddressError::InvalidLength)
+        );
+        assert_eq!(
+            "0xzaaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse::<Address>(),
+            Err(ParseAddressError::InvalidHex)
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use ethers::types::U256;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{ConversionError, IntoBigDecimal, TryFromBigDecimal};
+
+/// A denomination of a token, e.g. ether is 10^18 wei
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unit {
+    decimals: u32,
+    symbol: Cow<'static, str>,
+}
+
+impl Unit {
+    pub const WEI: Unit = Unit::from_static(0, "wei");
+    pub const GWEI: Unit = Unit::from_static(9, "gwei");
+    pub const ETHER: Unit = Unit::from_static(18, "ETH");
+
+    /// Largest number of decimals a `U256` amount can be meaningfully divided into
+    pub const MAX_DECIMALS: u32 = 77;
+
+    const fn from_static(decimals: u32, symbol: &'static str) -> Self {
+        Self {
+            decimals,
+            symbol: Cow::Borrowed(symbol),
+        }
+    }
+
+    /// Creates a unit for a token with `decimals` decimals, failing above `MAX_DECIMALS`
+    pub fn new(
+        decimals: u32,
+        symbol: impl Into<Cow<'static, str>>,
+    ) -> Result<Self, TooManyDecimals> {
+        if decimals > Self::MAX_DECIMALS {
+            return Err(TooManyDecimals);
+        }
+
+        Ok(Self {
+            decimals,
+            symbol: symbol.into(),
+        })
+    }
+
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Looks up one of the built-in units by symbol, ignoring case
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol.to_ascii_lowercase().as_str() {
+            "wei" => Some(Self::WEI),
+            "gwei" => Some(Self::GWEI),
+            "eth" | "ether" => Some(Self::ETHER),
+            _ => None,
+        }
+    }
+}
+
+/// Returned when a unit has more decimals than `Unit::MAX_DECIMALS`
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooManyDecimals;
+
+impl fmt::Display for TooManyDecimals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a unit has at most {} decimals", Unit::MAX_DECIMALS)
+    }
+}
+
+impl std::error::Error for TooManyDecimals {}
+
+/// Returned when a string is not a valid token amount
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseAmountError {
+    /// The string is not a non-negative decimal number
+    InvalidNumber,
+    /// The number has more decimals than the unit supports
+    TooPrecise,
+    /// The amount does not fit in a `U256`
+    Overflow,
+    /// The unit after the number is not one of wei, gwei or ether
+    UnknownUnit,
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNumber => write!(f, "amount must be a non-negative decimal number"),
+            Self::TooPrecise => write!(f, "amount has more decimals than its unit"),
+            Self::Overflow => write!(f, "amount is too large"),
+            Self::UnknownUnit => write!(f, "unit must be wei, gwei or ether"),
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+/// An exact amount of a token, counted in its smallest unit (wei for ether)
+///
+/// Arithmetic is checked rather than wrapping, and amounts serialize as a decimal string of wei
+/// so they survive JSON clients that parse numbers as floats.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TokenAmount(U256);
+
+impl TokenAmount {
+    pub const ZERO: TokenAmount = TokenAmount(U256::zero());
+
+    pub fn from_wei(wei: U256) -> Self {
+        Self(wei)
+    }
+
+    pub fn wei(&self) -> U256 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// Multiplies the amount by a count, e.g. a vote price by the number of votes
+    pub fn checked_mul(self, count: impl Into<U256>) -> Option<Self> {
+        self.0.checked_mul(count.into()).map(Self)
+    }
+
+    /// Formats the amount as a decimal number of `unit` without trailing zeros, e.g. `1.5`
+    pub fn to_decimal_string(&self, unit: &Unit) -> String {
+        let digits = self.0.to_string();
+        let decimals = unit.decimals as usize;
+        if decimals == 0 {
+            return digits;
+        }
+
+        let digits = format!("{:0>width$}", digits, width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            integer.to_owned()
+        } else {
+            format!("{}.{}", integer, fraction)
+        }
+    }
+
+    /// Formats the amount in `unit` followed by its symbol, e.g. `1.5 ETH`
+    pub fn format_units(&self, unit: &Unit) -> String {
+        format!("{} {}", self.to_decimal_string(unit), unit.symbol)
+    }
+
+    /// Parses a decimal number of `unit`, e.g. `1.5` ether, failing if it is not a whole number
+    /// of the smallest unit
+    pub fn parse_units(amount: &str, unit: &Unit) -> Result<Self, ParseAmountError> {
+        let (integer, fraction) = match amount.split_once('.') {
+            Some((integer, fraction)) if !fraction.is_empty() => (integer, fraction),
+            Some(_) => return Err(ParseAmountError::InvalidNumber),
+            None => (amount, ""),
+        };
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty() || !is_digits(integer) || !is_digits(fraction) {
+            return Err(ParseAmountError::InvalidNumber);
+        }
+
+        // trailing zeros never change the value, so only the significant decimals must fit
+        let fraction = fraction.trim_end_matches('0');
+        let decimals = unit.decimals as usize;
+        if fraction.len() > decimals {
+            return Err(ParseAmountError::TooPrecise);
+        }
+
+        let digits = format!("{}{:0<width$}", integer, fraction, width = decimals);
+        let digits = digits.trim_start_matches('0');
+        if digits.is_empty() {
+            return Ok(Self::ZERO);
+        }
+        U256::from_dec_str(digits)
+            .map(Self)
+            .map_err(|_| ParseAmountError::Overflow)
+    }
+}
+
+impl From<U256> for TokenAmount {
+    fn from(wei: U256) -> Self {
+        Self(wei)
+    }
+}
+
+impl From<TokenAmount> for U256 {
+    fn from(amount: TokenAmount) -> Self {
+        amount.0
+    }
+}
+
+impl IntoBigDecimal for TokenAmount {
+    fn into_bigdecimal(self) -> BigDecimal {
+        self.0.into_bigdecimal()
+    }
+}
+
+impl TryFromBigDecimal for TokenAmount {
+    fn try_from_bigdecimal(value: &BigDecimal) -> Result<Self, ConversionError> {
+        U256::try_from_bigdecimal(value).map(Self)
+    }
+}
+
+/// Prints the amount in wei
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} wei", self.0)
+    }
+}
+
+impl FromStr for TokenAmount {
+    type Err = ParseAmountError;
+
+    /// Parses either a bare number of wei or a number followed by a unit, e.g. `1.5 ether`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let amount = parts.next().ok_or(ParseAmountError::InvalidNumber)?;
+        let unit = match parts.next() {
+            Some(symbol) => Unit::from_symbol(symbol).ok_or(ParseAmountError::UnknownUnit)?,
+            None => Unit::WEI,
+        };
+        if parts.next().is_some() {
+            return Err(ParseAmountError::InvalidNumber);
+        }
+
+        Self::parse_units(amount, &unit)
+    }
+}
+
+impl Serialize for TokenAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl de::Visitor<'_> for AmountVisitor {
+            type Value = TokenAmount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number of wei or a string such as \"1.5 ether\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, wei: u64) -> Result<Self::Value, E> {
+                Ok(TokenAmount(U256::from(wei)))
+            }
+
+            fn visit_str<E: de::Error>(self, amount: &str) -> Result<Self::Value, E> {
+                amount.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_in_units() {
+        let amount = TokenAmount::from_wei(U256::from(1_500_000_000_000_000_000u64));
+        assert_eq!(amount.format_units(&Unit::ETHER), "1.5 ETH");
+        assert_eq!(amount.to_decimal_string(&Unit::GWEI), "1500000000");
+        assert_eq!(amount.to_string(), "1500000000000000000");
+
+        let dust = TokenAmount::from_wei(U256::from(1));
+        assert_eq!(dust.to_decimal_string(&Unit::ETHER), "0.000000000000000001");
+        assert_eq!(
+            TokenAmount::ZERO.format_units(&Unit::new(6, "USDC").unwrap()),
+            "0 USDC"
+        );
+        assert_eq!(Unit::new(78, "TOO"), Err(TooManyDecimals));
+    }
+
+    #[test]
+    fn parses_units() {
+        let amount = TokenAmount::from_wei(U256::from(1_500_000_000_000_000_000u64));
+        assert_eq!("1.5 ether".parse(), Ok(amount));
+        assert_eq!("1.50 ETH".parse(), Ok(amount));
+        assert_eq!("1500000000 gwei".parse(), Ok(amount));
+        assert_eq!("1500000000000000000".parse(), Ok(amount));
+
+        assert_eq!(
+            "0.5 wei".parse::<TokenAmount>(),
+            Err(ParseAmountError::TooPrecise)
+        );
+        assert_eq!(
+            "-1 ether".parse::<TokenAmount>(),
+            Err(ParseAmountError::InvalidNumber)
+        );
+        assert_eq!(
+            "1 btc".parse::<TokenAmount>(),
+            Err(ParseAmountError::UnknownUnit)
+        );
+        assert_eq!(
+            format!("{}0", U256::MAX).parse::<TokenAmount>(),
+            Err(ParseAmountError::Overflow)
+        );
+    }
+
+    #[test]
+    fn checks_arithmetic() {
+        let price = TokenAmount::from_wei(U256::from(10).pow(U256::from(18)));
+        assert_eq!(
+            price
+                .checked_mul(3u64)
+                .map(|payout| payout.format_units(&Unit::ETHER)),
+            Some("3 ETH".to_owned())
+        );
+        assert_eq!(TokenAmount::ZERO.checked_sub(price), None);
+        assert_eq!(price.checked_mul(U256::MAX), None);
+    }
+}
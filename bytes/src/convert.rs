@@ -0,0 +1,185 @@
+use std::fmt;
+
+use bigdecimal::BigDecimal;
+use ethers::types::{H256, U128, U256, U64};
+use num_bigint::{BigInt, Sign};
+use num_traits::{ToPrimitive, Zero};
+
+/// Number of decimal digits in `U256::MAX`, the widest supported type
+const MAX_DIGITS: i64 = 78;
+
+/// Returned when a `BigDecimal` cannot be represented by the target type
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConversionError {
+    /// The value is below zero but the target type is unsigned
+    Negative,
+    /// The value has a non-zero fractional part
+    Fractional,
+    /// The value does not fit in the target type
+    Overflow,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Negative => write!(f, "value is negative"),
+            Self::Fractional => write!(f, "value is not an integer"),
+            Self::Overflow => write!(f, "value is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Lossless conversion into a `BigDecimal`
+pub trait IntoBigDecimal {
+    fn into_bigdecimal(self) -> BigDecimal;
+}
+
+/// Range-checked conversion from a `BigDecimal`
+pub trait TryFromBigDecimal: Sized {
+    fn try_from_bigdecimal(value: &BigDecimal) -> Result<Self, ConversionError>;
+}
+
+/// Returns the integer a `BigDecimal` holds, rejecting values with a fractional part
+///
+/// Scaled values such as `1.500e3` or `100.00` are normalized first, so only the numeric value
+/// matters and not how it is written.
+fn to_bigint(value: &BigDecimal) -> Result<BigInt, ConversionError> {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    if digits.is_zero() {
+        return Ok(BigInt::zero());
+    }
+
+    // reject extreme exponents before scaling, as `10^scale` would take unbounded memory
+    if scale < -MAX_DIGITS {
+        return Err(ConversionError::Overflow);
+    }
+    // a non-zero value with fewer bits than its scale is smaller than `10^scale` in magnitude
+    if scale > 0 && scale as u64 >= digits.bits() {
+        return Err(ConversionError::Fractional);
+    }
+
+    if scale <= 0 {
+        let factor = BigInt::from(10u8).pow(scale.unsigned_abs() as u32);
+        return Ok(digits * factor);
+    }
+
+    let divisor = BigInt::from(10u8).pow(scale as u32);
+    let remainder = &digits % &divisor;
+    if !remainder.is_zero() {
+        return Err(ConversionError::Fractional);
+    }
+    Ok(digits / divisor)
+}
+
+/// Returns the little-endian bytes of a non-negative integer that fits in `width` bytes
+fn to_unsigned_le(value: &BigDecimal, width: usize) -> Result<Vec<u8>, ConversionError> {
+    let bigint = to_bigint(value)?;
+    if bigint.sign() == Sign::Minus {
+        return Err(ConversionError::Negative);
+    }
+
+    let (_, mut bytes) = bigint.to_bytes_le();
+    if bytes.len() > width {
+        return Err(ConversionError::Overflow);
+    }
+    bytes.resize(width, 0);
+    Ok(bytes)
+}
+
+macro_rules! impl_uint_conversions {
+    ($uint:ty, $width:expr) => {
+        impl IntoBigDecimal for $uint {
+            fn into_bigdecimal(self) -> BigDecimal {
+                let mut bytes = [0u8; $width];
+                self.to_little_endian(&mut bytes);
+                BigDecimal::from(BigInt::from_bytes_le(Sign::Plus, &bytes))
+            }
+        }
+
+        impl TryFromBigDecimal for $uint {
+            fn try_from_bigdecimal(value: &BigDecimal) -> Result<Self, ConversionError> {
+                Ok(<$uint>::from_little_endian(&to_unsigned_le(value, $width)?))
+            }
+        }
+    };
+}
+
+impl_uint_conversions!(U64, 8);
+impl_uint_conversions!(U128, 16);
+impl_uint_conversions!(U256, 32);
+
+/// Hashes are read as unsigned big-endian integers, matching `uint256` values in the schema
+impl IntoBigDecimal for H256 {
+    fn into_bigdecimal(self) -> BigDecimal {
+        BigDecimal::from(BigInt::from_bytes_be(Sign::Plus, self.as_bytes()))
+    }
+}
+
+impl TryFromBigDecimal for H256 {
+    fn try_from_bigdecimal(value: &BigDecimal) -> Result<Self, ConversionError> {
+        let mut bytes = to_unsigned_le(value, 32)?;
+        bytes.reverse();
+        Ok(H256::from_slice(&bytes))
+    }
+}
+
+impl IntoBigDecimal for i64 {
+    fn into_bigdecimal(self) -> BigDecimal {
+        BigDecimal::from(self)
+    }
+}
+
+impl TryFromBigDecimal for i64 {
+    fn try_from_bigdecimal(value: &BigDecimal) -> Result<Self, ConversionError> {
+        to_bigint(value)?.to_i64().ok_or(ConversionError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn handles_scaled_decimals() {
+        let scaled = BigDecimal::from_str("1.500e3").unwrap();
+        assert_eq!(U64::try_from_bigdecimal(&scaled), Ok(U64::from(1500)));
+
+        let padded = BigDecimal::from_str("100.00").unwrap();
+        assert_eq!(i64::try_from_bigdecimal(&padded), Ok(100));
+
+        let fractional = BigDecimal::from_str("1.5").unwrap();
+        assert_eq!(
+            U256::try_from_bigdecimal(&fractional),
+            Err(ConversionError::Fractional)
+        );
+    }
+
+    #[test]
+    fn range_checks_values() {
+        assert_eq!(
+            U64::try_from_bigdecimal(&BigDecimal::from(-1)),
+            Err(ConversionError::Negative)
+        );
+        assert_eq!(
+            U64::try_from_bigdecimal(
+                &(U128::from(u64::MAX).into_bigdecimal() + BigDecimal::from(1))
+            ),
+            Err(ConversionError::Overflow)
+        );
+        assert_eq!(
+            U256::try_from_bigdecimal(&U256::MAX.into_bigdecimal()),
+            Ok(U256::MAX)
+        );
+    }
+
+    #[test]
+    fn reads_hashes_as_big_endian() {
+        let hash = H256::from_low_u64_be(42);
+        assert_eq!(hash.into_bigdecimal(), BigDecimal::from(42));
+        assert_eq!(H256::try_from_bigdecimal(&BigDecimal::from(42)), Ok(hash));
+    }
+}
@@ -1,3 +1,13 @@
+mod address;
+mod amount;
+mod convert;
+mod symbol;
+
+pub use address::{Address, ParseAddressError};
+pub use amount::{ParseAmountError, TokenAmount, TooManyDecimals, Unit};
+pub use convert::{ConversionError, IntoBigDecimal, TryFromBigDecimal};
+pub use symbol::{Symbol, SymbolError};
+
 use bigdecimal::BigDecimal;
 use byte_slice_cast::AsByteSlice;
 use ethers::types::U64;
@@ -9,12 +19,112 @@ pub fn bytes_to_bigdecimal<T>(byte_slice: impl AsByteSlice<T>) -> BigDecimal {
     BigDecimal::from(bigint)
 }
 
-/// Converts a `BigDecimal` to a byte slice
-pub fn bigdecimal_to_bytes(bigdecimal: BigDecimal) -> U64 {
-    let (bigint, _) = bigdecimal.as_bigint_and_exponent();
-    let (_, bytes) = bigint.to_bytes_le();
-    let mut array = [0u8; 8];
-    array[..bytes.len()].copy_from_slice(&bytes);
-    U64::from_little_endian(&array)
+/// Converts a `BigDecimal` to a `U64`, failing if it is negative, fractional or too large
+pub fn bigdecimal_to_bytes(bigdecimal: BigDecimal) -> Result<U64, ConversionError> {
+    U64::try_from_bigdecimal(&bigdecimal)
 }
 
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H256, U128, U256};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn any_u256() -> impl Strategy<Value = U256> {
+        any::<[u8; 32]>().prop_map(|bytes| U256::from_little_endian(&bytes))
+    }
+
+    #[test]
+    fn converts_edge_cases() {
+        assert_eq!(bytes_to_bigdecimal(U64::zero()), BigDecimal::from(0));
+        assert_eq!(bigdecimal_to_bytes(BigDecimal::from(0)), Ok(U64::zero()));
+        assert_eq!(
+            bigdecimal_to_bytes(bytes_to_bigdecimal(U64::MAX)),
+            Ok(U64::MAX)
+        );
+        assert_eq!(
+            bigdecimal_to_bytes(U128::from(u64::MAX).into_bigdecimal() + BigDecimal::from(1)),
+            Err(ConversionError::Overflow)
+        );
+        assert_eq!(
+            bigdecimal_to_bytes("1e100000000000".parse().unwrap()),
+            Err(ConversionError::Overflow)
+        );
+        assert_eq!(
+            bigdecimal_to_bytes("1e-100000000000".parse().unwrap()),
+            Err(ConversionError::Fractional)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn u64_round_trips(n: u64) {
+            let value = bytes_to_bigdecimal(U64::from(n));
+            prop_assert_eq!(&value, &BigDecimal::from(n));
+            prop_assert_eq!(bigdecimal_to_bytes(value), Ok(U64::from(n)));
+        }
+
+        #[test]
+        fn u128_round_trips(n: u128) {
+            let value = U128::from(n).into_bigdecimal();
+            prop_assert_eq!(&value, &bytes_to_bigdecimal(U128::from(n)));
+            prop_assert_eq!(U128::try_from_bigdecimal(&value), Ok(U128::from(n)));
+        }
+
+        #[test]
+        fn u256_round_trips(n in any_u256()) {
+            let value = n.into_bigdecimal();
+            prop_assert_eq!(&value, &bytes_to_bigdecimal(n));
+            prop_assert_eq!(U256::try_from_bigdecimal(&value), Ok(n));
+        }
+
+        #[test]
+        fn h256_round_trips(bytes: [u8; 32]) {
+            let hash = H256::from(bytes);
+            let value = hash.into_bigdecimal();
+            prop_assert_eq!(&value, &U256::from_big_endian(&bytes).into_bigdecimal());
+            prop_assert_eq!(H256::try_from_bigdecimal(&value), Ok(hash));
+        }
+
+        #[test]
+        fn i64_round_trips(n: i64) {
+            prop_assert_eq!(i64::try_from_bigdecimal(&n.into_bigdecimal()), Ok(n));
+        }
+
+        #[test]
+        fn scale_does_not_change_the_value(n in any_u256(), zeros in 0u32..40) {
+            // the same integer written with trailing zeros after the decimal point
+            let (digits, _) = n.into_bigdecimal().into_bigint_and_exponent();
+            let digits = digits * BigInt::from(10u8).pow(zeros);
+            let scaled = BigDecimal::new(digits, zeros as i64);
+            prop_assert_eq!(U256::try_from_bigdecimal(&scaled), Ok(n));
+        }
+
+        #[test]
+        fn rejects_fractions(n: u64, fraction in 1u64..10) {
+            let value = BigDecimal::new((n as i128 * 10 + fraction as i128).into(), 1);
+            prop_assert_eq!(
+                bigdecimal_to_bytes(value.clone()),
+                Err(ConversionError::Fractional)
+            );
+            prop_assert_eq!(i64::try_from_bigdecimal(&value), Err(ConversionError::Fractional));
+        }
+
+        #[test]
+        fn rejects_negatives(n in 1u64..) {
+            let value = -BigDecimal::from(n);
+            prop_assert_eq!(
+                bigdecimal_to_bytes(value.clone()),
+                Err(ConversionError::Negative)
+            );
+            prop_assert_eq!(U256::try_from_bigdecimal(&value), Err(ConversionError::Negative));
+        }
+
+        #[test]
+        fn rejects_values_wider_than_the_target(n in (u64::MAX as u128 + 1)..) {
+            let value = U128::from(n).into_bigdecimal();
+            prop_assert_eq!(bigdecimal_to_bytes(value), Err(ConversionError::Overflow));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::str::{self, FromStr};
+
+/// Zero width joiner, which glues emojis into sequences such as families
+const ZWJ: char = '\u{200d}';
+
+/// Variation selector asking for the emoji presentation of the preceding character
+const VS16: char = '\u{fe0f}';
+
+/// A vote symbol, stored on chain as `bytes4`
+///
+/// Clients encode an emoji as the first four bytes of its UTF-8 form padded with zeros, so
+/// emojis longer than four bytes such as "🌶️" or ZWJ sequences are cut short and may end in a
+/// partial UTF-8 sequence. The raw bytes are always kept so a symbol converts back exactly.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol([u8; 4]);
+
+/// Returned when a symbol is not a valid emoji
+#[derive(Debug, PartialEq, Eq)]
+pub enum SymbolError {
+    /// The symbol is only padding
+    Empty,
+    /// The symbol contains bytes that are not UTF-8, beyond a partial trailing character
+    InvalidUtf8,
+    /// The symbol is text but not an emoji
+    NotEmoji,
+    /// The emoji is no longer recognizable once cut to four bytes, e.g. flags and keycaps
+    Unrepresentable,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "symbol is empty"),
+            Self::InvalidUtf8 => write!(f, "symbol is not valid UTF-8"),
+            Self::NotEmoji => write!(f, "symbol is not an emoji"),
+            Self::Unrepresentable => write!(f, "emoji does not fit in a symbol"),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl Symbol {
+    /// Reads a symbol from a byte column, padding or truncating it to four bytes
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let mut symbol = [0u8; 4];
+        let len = bytes.len().min(4);
+        symbol[..len].copy_from_slice(&bytes[..len]);
+        Self(symbol)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
+
+    /// Returns the text the symbol holds, without padding or a partial trailing character
+    pub fn text(&self) -> Result<&str, SymbolError> {
+        let len = self.0.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        let bytes = &self.0[..len];
+        if bytes.is_empty() {
+            return Err(SymbolError::Empty);
+        }
+
+        let text = match str::from_utf8(bytes) {
+            Ok(text) => text,
+            // an incomplete sequence at the end is a character that was cut off, not corruption
+            Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => {
+                str::from_utf8(&bytes[..e.valid_up_to()]).map_err(|_| SymbolError::InvalidUtf8)?
+            }
+            Err(_) => return Err(SymbolError::InvalidUtf8),
+        };
+        Ok(text)
+    }
+
+    /// Returns the emoji the symbol was encoded from
+    ///
+    /// When the emoji was cut short, this is the emoji its first character stands for, e.g. a
+    /// family sequence decodes to its first member.
+    pub fn emoji(&self) -> Result<&'static emojis::Emoji, SymbolError> {
+        let text = self.text()?;
+        emojis::get(text)
+            .or_else(|| emojis::get(text.trim_end_matches([ZWJ, VS16])))
+            .ok_or(SymbolError::NotEmoji)
+    }
+}
+
+impl From<[u8; 4]> for Symbol {
+    fn from(bytes: [u8; 4]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Symbol> for [u8; 4] {
+    fn from(symbol: Symbol) -> Self {
+        symbol.0
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = SymbolError;
+
+    /// Encodes an emoji the way clients do, from its fully qualified form so that "🌶" and "🌶️"
+    /// give the same symbol
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(SymbolError::Empty);
+        }
+        let emoji = emojis::get(s).ok_or(SymbolError::NotEmoji)?;
+
+        let symbol = Self::from_slice(emoji.as_bytes());
+        if symbol.emoji().is_err() {
+            return Err(SymbolError::Unrepresentable);
+        }
+        Ok(symbol)
+    }
+}
+
+/// Prints the fully qualified emoji, or U+FFFD when the symbol is not an emoji
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.emoji() {
+            Ok(emoji) => f.write_str(emoji.as_str()),
+            Err(_) => f.write_str("\u{fffd}"),
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol(0x")?;
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_truncated_emojis() {
+        // "🌶️" is seven bytes, so the variation selector is cut off
+        let pepper: Symbol = "🌶️".parse().unwrap();
+        assert_eq!(pepper.as_bytes(), &[0xf0, 0x9f, 0x8c, 0xb6]);
+        assert_eq!(pepper.to_string(), "🌶️");
+        assert_eq!("🌶".parse(), Ok(pepper));
+
+        // "☀️" keeps one byte of its variation selector
+        let sun: Symbol = "☀️".parse().unwrap();
+        assert_eq!(sun.text(), Ok("☀"));
+        assert_eq!(sun.to_string(), "☀️");
+
+        let padded = Symbol::from([0xe2, 0x9c, 0x8c, 0x00]);
+        assert_eq!(padded.to_string(), "✌️");
+    }
+
+    #[test]
+    fn flags_invalid_symbols() {
+        assert_eq!(Symbol::default().emoji().err(), Some(SymbolError::Empty));
+        assert_eq!(
+            Symbol::from(*b"abc\0").emoji().err(),
+            Some(SymbolError::NotEmoji)
+        );
+        assert_eq!(
+            Symbol::from([0xff, 0x9f, 0x8c, 0xb6]).emoji().err(),
+            Some(SymbolError::InvalidUtf8)
+        );
+        assert_eq!(Symbol::from(*b"abc\0").to_string(), "\u{fffd}");
+        assert_eq!("a".parse::<Symbol>(), Err(SymbolError::NotEmoji));
+    }
+
+    #[test]
+    fn round_trips_every_emoji() {
+        for emoji in emojis::iter() {
+            let symbol = match emoji.as_str().parse::<Symbol>() {
+                Ok(symbol) => symbol,
+                Err(SymbolError::Unrepresentable) => continue,
+                Err(e) => panic!("{} failed to encode: {}", emoji.as_str(), e),
+            };
+            let decoded = symbol.emoji().unwrap();
+            if emoji.as_bytes().len() <= 4 {
+                assert_eq!(decoded.as_str(), emoji.as_str());
+            }
+            assert_eq!(decoded.as_str().parse(), Ok(symbol), "{}", emoji.as_str());
+        }
+        assert_eq!("🇺🇸".parse::<Symbol>(), Err(SymbolError::Unrepresentable));
+    }
+}
@@ -0,0 +1,6 @@
+-- Add down migration script here
+drop index if exists votes_placer_chain_id_block_number_id_idx;
+
+drop index if exists votes_cycle_id_chain_id_block_number_id_idx;
+
+drop index if exists cycles_chain_id_starting_block_id_idx;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+create index if not exists cycles_chain_id_starting_block_id_idx
+on cycles (chain_id, starting_block desc, id desc);
+
+create index if not exists votes_cycle_id_chain_id_block_number_id_idx
+on votes (cycle_id, chain_id, block_number desc, id desc);
+
+create index if not exists votes_placer_chain_id_block_number_id_idx
+on votes (placer, chain_id, block_number desc, id desc);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop index if exists votes_chain_id_cycle_id_symbol_block_number_idx;
+
+drop table if exists cycle_symbol_totals;
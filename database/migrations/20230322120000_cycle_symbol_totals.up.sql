@@ -0,0 +1,27 @@
+-- Add up migration script here
+create table if not exists cycle_symbol_totals (
+  chain_id uint256 not null,
+  cycle_id uint256 not null references cycles (id) on delete cascade,
+  symbol bytes4 not null,
+  amount uint256 not null,
+  votes bigint not null,
+  max_block uint64 not null,
+  primary key (chain_id, cycle_id, symbol)
+);
+
+create index if not exists votes_chain_id_cycle_id_symbol_block_number_idx
+on votes (chain_id, cycle_id, symbol, block_number desc);
+
+insert into cycle_symbol_totals (chain_id, cycle_id, symbol, amount, votes, max_block)
+select
+  chain_id,
+  cycle_id,
+  symbol,
+  sum(amount),
+  count(*),
+  max(block_number)
+from votes
+group by
+  chain_id,
+  cycle_id,
+  symbol;
@@ -0,0 +1,10 @@
+-- Add down migration script here
+drop table if exists orphaned_claims;
+
+drop table if exists orphaned_votes;
+
+drop table if exists orphaned_cycles;
+
+drop table if exists reorgs;
+
+alter table votes drop column if exists claimed_block;
@@ -0,0 +1,46 @@
+-- Add up migration script here
+alter table votes add column if not exists claimed_block uint64;
+
+create table if not exists reorgs (
+  id bigserial primary key,
+  chain_id uint256 not null,
+  from_block uint64 not null,
+  to_block uint64 not null,
+  detected_at timestamptz not null default now()
+);
+
+create table if not exists orphaned_cycles (
+  reorg_id bigint not null references reorgs (id),
+  id uint256 not null,
+  chain_id uint256 not null,
+  block_number uint64 not null,
+  creator address not null,
+  starting_block uint64 not null,
+  block_length uint64 not null,
+  vote_price uint256 not null,
+  balance uint128 not null,
+  primary key (reorg_id, id)
+);
+
+create table if not exists orphaned_votes (
+  reorg_id bigint not null references reorgs (id),
+  id uint256 not null,
+  chain_id uint256 not null,
+  block_number uint64 not null,
+  cycle_id uint256 not null,
+  symbol bytes4 not null,
+  claimed boolean not null,
+  amount uint56 not null,
+  placer address not null,
+  primary key (reorg_id, id)
+);
+
+create index if not exists orphaned_votes_placer_idx on orphaned_votes (placer);
+
+create table if not exists orphaned_claims (
+  reorg_id bigint not null references reorgs (id),
+  vote_id uint256 not null,
+  chain_id uint256 not null,
+  claimed_block uint64 not null,
+  primary key (reorg_id, vote_id)
+);
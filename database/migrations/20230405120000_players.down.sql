@@ -0,0 +1,10 @@
+-- Add down migration script here
+drop table if exists players;
+
+alter table orphaned_claims drop column if exists reward;
+
+alter table votes drop column if exists reward;
+
+drop index if exists votes_unplaced_idx;
+
+alter table votes drop column if exists placement;
@@ -0,0 +1,26 @@
+-- Add up migration script here
+-- votes indexed before placements were recorded have none until the indexer backfills them
+alter table votes add column if not exists placement uint256;
+
+create index if not exists votes_unplaced_idx on votes (chain_id, block_number)
+where placement is null;
+
+alter table votes add column if not exists reward uint256;
+
+alter table orphaned_claims add column if not exists reward uint256;
+
+create table if not exists players (
+  chain_id uint256 not null,
+  address address not null,
+  first_seen_block uint64 not null,
+  cycles_played bigint not null,
+  total_votes bigint not null,
+  total_spent numeric not null,
+  total_rewards numeric not null,
+  net_pnl numeric not null,
+  favorite_symbol bytes4 not null,
+  best_placement uint256,
+  primary key (chain_id, address)
+);
+
+create index if not exists players_chain_id_net_pnl_idx on players (chain_id, net_pnl desc);
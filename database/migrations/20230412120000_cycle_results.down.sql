@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop table if exists cycle_result_standings;
+
+drop table if exists cycle_results;
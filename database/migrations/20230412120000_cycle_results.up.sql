@@ -0,0 +1,20 @@
+-- Add up migration script here
+create table if not exists cycle_results (
+  cycle_id uint256 primary key references cycles (id) on delete cascade,
+  chain_id uint256 not null,
+  winning_symbol bytes4,
+  total_pot uint256 not null,
+  winning_votes bigint not null,
+  finalized_block uint64 not null
+);
+
+create table if not exists cycle_result_standings (
+  cycle_id uint256 not null references cycles (id) on delete cascade,
+  chain_id uint256 not null,
+  symbol bytes4 not null,
+  amount uint256 not null,
+  votes bigint not null,
+  max_block uint64 not null,
+  rank integer not null,
+  primary key (cycle_id, symbol)
+);
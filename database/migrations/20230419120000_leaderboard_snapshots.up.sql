@@ -0,0 +1,12 @@
+-- Add up migration script here
+create table if not exists leaderboard_snapshots (
+  chain_id uint256 not null,
+  cycle_id uint256 not null references cycles (id) on delete cascade,
+  block_number uint64 not null,
+  symbols bytea[] not null,
+  amounts numeric[] not null,
+  primary key (chain_id, cycle_id, block_number)
+);
+
+create index if not exists leaderboard_snapshots_chain_id_block_number_idx
+on leaderboard_snapshots (chain_id, block_number);
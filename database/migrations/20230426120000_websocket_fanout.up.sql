@@ -0,0 +1,13 @@
+-- Add up migration script here
+create unlogged table if not exists published_leaderboards (
+  topic text primary key,
+  message text not null,
+  published_at timestamptz not null default now()
+);
+
+create unlogged table if not exists server_instances (
+  instance_id text primary key,
+  online integer not null,
+  topics text[] not null,
+  seen_at timestamptz not null default now()
+);
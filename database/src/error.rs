@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// Postgres SQLSTATE class for integrity constraint violations
+const INTEGRITY_CONSTRAINT_VIOLATION: &str = "23";
+
+/// Postgres SQLSTATE for a statement cancelled by `statement_timeout`
+const QUERY_CANCELED: &str = "57014";
+
+/// Errors returned by `Database` methods
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The requested row does not exist, e.g. there is no current cycle
+    NotFound,
+    /// A write violated a unique, foreign key or check constraint
+    ConstraintViolation {
+        constraint: Option<String>,
+        message: String,
+    },
+    /// The database could not be reached or the connection was lost
+    Connection(sqlx::Error),
+    /// A connection could not be acquired or the statement ran out of time
+    Timeout,
+    /// Any other error reported by the database driver
+    Other(sqlx::Error),
+}
+
+impl DatabaseError {
+    /// Whether the failed operation may succeed if it is attempted again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Connection(_) | Self::Timeout)
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "row not found"),
+            Self::ConstraintViolation {
+                constraint: Some(constraint),
+                message,
+            } => write!(f, "constraint `{}` violated: {}", constraint, message),
+            Self::ConstraintViolation { message, .. } => {
+                write!(f, "constraint violated: {}", message)
+            }
+            Self::Connection(e) => write!(f, "database connection failed: {}", e),
+            Self::Timeout => write!(f, "database operation timed out"),
+            Self::Other(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Connection(e) | Self::Other(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::PoolTimedOut => Self::Timeout,
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolClosed => {
+                Self::Connection(error)
+            }
+            sqlx::Error::Database(ref db_error) => match db_error.code() {
+                Some(code) if code.starts_with(INTEGRITY_CONSTRAINT_VIOLATION) => {
+                    Self::ConstraintViolation {
+                        constraint: db_error.constraint().map(str::to_owned),
+                        message: db_error.message().to_owned(),
+                    }
+                }
+                Some(code) if code == QUERY_CANCELED => Self::Timeout,
+                _ => Self::Other(error),
+            },
+            _ => Self::Other(error),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DatabaseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_driver_errors() {
+        assert!(matches!(
+            DatabaseError::from(sqlx::Error::RowNotFound),
+            DatabaseError::NotFound
+        ));
+        assert!(matches!(
+            DatabaseError::from(sqlx::Error::PoolTimedOut),
+            DatabaseError::Timeout
+        ));
+        assert!(DatabaseError::from(sqlx::Error::PoolClosed).is_retryable());
+        assert!(!DatabaseError::from(sqlx::Error::RowNotFound).is_retryable());
+    }
+}
@@ -1,5 +1,14 @@
 pub mod database;
+pub mod error;
 pub mod models;
+pub mod pagination;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-pub use crate::database::Database;
-pub use crate::models::{Cycle, Vote};
+pub use crate::database::{AdvisoryLock, Database, Listener};
+pub use crate::error::DatabaseError;
+pub use crate::models::{
+    Claim, Cycle, CycleResult, LeaderboardSnapshot, OrphanedVote, Player, PlayerClaim, Reorg,
+    ServerInstance, Standing, Vote,
+};
+pub use crate::pagination::{Cursor, Page};
@@ -1,3 +1,5 @@
+use bytes::Address;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::BigDecimal;
 
 #[derive(sqlx::Type)]
@@ -6,7 +8,7 @@ pub struct Cycle {
     pub id: BigDecimal,
     pub chain_id: BigDecimal,
     pub block_number: BigDecimal,
-    pub creator: String,
+    pub creator: Address,
     pub starting_block: BigDecimal,
     pub block_length: BigDecimal,
     pub vote_price: BigDecimal,
@@ -14,16 +16,17 @@ pub struct Cycle {
     pub current: bool,
 }
 
-#[derive(sqlx::Type)]
-#[sqlx(type_name = "vote")]
 pub struct Vote {
     pub id: BigDecimal,
     pub chain_id: BigDecimal,
     pub block_number: BigDecimal,
     pub cycle_id: BigDecimal,
-    pub placer: String,
+    pub placer: Address,
     pub symbol: [u8; 4],
     pub amount: BigDecimal,
+    // `None` for votes indexed before placements were recorded, until they are backfilled
+    pub placement: Option<BigDecimal>,
+    pub claimed: bool,
 }
 
 #[derive(sqlx::Type)]
@@ -32,3 +35,92 @@ pub struct Leaderboard {
     pub amount: Option<BigDecimal>,
     pub max_block: Option<BigDecimal>,
 }
+
+pub struct Claim {
+    pub vote_id: BigDecimal,
+    pub chain_id: BigDecimal,
+    pub block_number: BigDecimal,
+    pub reward: BigDecimal,
+}
+
+pub struct Reorg {
+    pub id: i64,
+    pub chain_id: BigDecimal,
+    pub from_block: BigDecimal,
+    pub to_block: BigDecimal,
+    pub detected_at: DateTime<Utc>,
+}
+
+pub struct OrphanedVote {
+    pub reorg_id: i64,
+    pub id: BigDecimal,
+    pub chain_id: BigDecimal,
+    pub block_number: BigDecimal,
+    pub cycle_id: BigDecimal,
+    pub placer: Address,
+    pub symbol: [u8; 4],
+    pub amount: BigDecimal,
+    pub claimed: bool,
+}
+
+pub struct Player {
+    pub chain_id: BigDecimal,
+    pub address: Address,
+    pub first_seen_block: BigDecimal,
+    pub cycles_played: i64,
+    pub total_votes: i64,
+    pub total_spent: BigDecimal,
+    pub total_rewards: BigDecimal,
+    pub net_pnl: BigDecimal,
+    pub favorite_symbol: [u8; 4],
+    pub best_placement: Option<BigDecimal>,
+}
+
+pub struct PlayerClaim {
+    pub vote_id: BigDecimal,
+    pub cycle_id: BigDecimal,
+    pub symbol: [u8; 4],
+    pub amount: BigDecimal,
+    pub claimed: bool,
+    // `None` until the vote is claimed
+    pub claimed_block: Option<BigDecimal>,
+    // the claimed reward, or the projected one while the vote is claimable
+    pub reward: Option<BigDecimal>,
+}
+
+pub struct CycleResult {
+    pub cycle_id: BigDecimal,
+    pub chain_id: BigDecimal,
+    // `None` when no votes were placed in the cycle
+    pub winning_symbol: Option<[u8; 4]>,
+    pub total_pot: BigDecimal,
+    pub winning_votes: i64,
+    pub finalized_block: BigDecimal,
+    // final per-symbol totals, winner first
+    pub standings: Vec<Standing>,
+}
+
+pub struct Standing {
+    pub symbol: [u8; 4],
+    pub amount: BigDecimal,
+    pub votes: i64,
+    pub max_block: BigDecimal,
+    pub rank: i32,
+}
+
+pub struct LeaderboardSnapshot {
+    pub chain_id: BigDecimal,
+    pub cycle_id: BigDecimal,
+    pub block_number: BigDecimal,
+    // symbols and their totals as of `block_number`, largest total first
+    pub symbols: Vec<Vec<u8>>,
+    pub amounts: Vec<BigDecimal>,
+}
+
+pub struct ServerInstance {
+    pub instance_id: String,
+    // websocket connections open on the instance
+    pub online: i32,
+    // leaderboard topics the instance has subscribers for
+    pub topics: Vec<String>,
+}
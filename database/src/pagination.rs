@@ -0,0 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+use sqlx::types::BigDecimal;
+
+/// Largest page size that listing methods will return
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// A page of rows along with the cursor that fetches the page after it
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // `None` when this is the last page
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from rows fetched with a limit of `limit + 1`, using the extra row only to
+    /// tell whether another page exists
+    pub(crate) fn from_rows(mut rows: Vec<T>, limit: i64, key: impl Fn(&T) -> Cursor) -> Self {
+        let limit = limit as usize;
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(key)
+        } else {
+            None
+        };
+
+        Self {
+            items: rows,
+            next_cursor,
+        }
+    }
+}
+
+/// Keyset position of the last row of a page
+///
+/// Rows are ordered by a block number and then by id, both descending, so the cursor stays
+/// stable while new rows are indexed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub block: BigDecimal,
+    pub id: BigDecimal,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.block, self.id)
+    }
+}
+
+/// Returned when a cursor string is not in the `<block>_<id>` form
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCursorError;
+
+impl fmt::Display for ParseCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cursor")
+    }
+}
+
+impl std::error::Error for ParseCursorError {}
+
+impl FromStr for Cursor {
+    type Err = ParseCursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (block, id) = s.split_once('_').ok_or(ParseCursorError)?;
+        let parse = |part: &str| {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseCursorError);
+            }
+            BigDecimal::from_str(part).map_err(|_| ParseCursorError)
+        };
+
+        Ok(Self {
+            block: parse(block)?,
+            id: parse(id)?,
+        })
+    }
+}
+
+/// Clamps a requested page size to `1..=MAX_PAGE_SIZE`
+pub(crate) fn page_size(limit: i64) -> i64 {
+    limit.clamp(1, MAX_PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            block: BigDecimal::from(16673866),
+            id: BigDecimal::from(42),
+        };
+        assert_eq!(cursor.to_string().parse(), Ok(cursor));
+        assert_eq!("16673866".parse::<Cursor>(), Err(ParseCursorError));
+        assert_eq!("1.5_2".parse::<Cursor>(), Err(ParseCursorError));
+    }
+
+    #[test]
+    fn page_reports_next_cursor_only_when_more_rows_exist() {
+        let key = |n: &i64| Cursor {
+            block: BigDecimal::from(*n),
+            id: BigDecimal::from(*n),
+        };
+
+        let page = Page::from_rows(vec![3, 2, 1], 2, key);
+        assert_eq!(page.items, vec![3, 2]);
+        assert_eq!(page.next_cursor, Some(key(&2)));
+
+        let page = Page::from_rows(vec![3, 2], 2, key);
+        assert_eq!(page.next_cursor, None);
+    }
+}
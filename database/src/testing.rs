@@ -0,0 +1,133 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sqlx::types::BigDecimal;
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::sync::OnceCell;
+
+use super::{Cycle, Database, Vote};
+
+/// Name prefix of the databases created by `setup_db`
+const SCRATCH_PREFIX: &str = "racer_test_";
+
+/// Distinguishes the databases created by one test process
+static NEXT_SCRATCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Set once the databases left behind by earlier test runs have been dropped
+static CLEANED: OnceCell<()> = OnceCell::const_new();
+
+/// Creates an empty database with every migration applied on the server at `DATABASE_URL`, so
+/// each test gets its own tables and tests can run in parallel
+pub async fn setup_db() -> Database {
+    let url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+    let mut admin = PgConnection::connect(&url)
+        .await
+        .expect("Connection failed for DATABASE_URL");
+
+    CLEANED
+        .get_or_init(|| async { drop_scratch_databases(&mut admin).await })
+        .await;
+
+    let name = format!(
+        "{}{}_{}",
+        SCRATCH_PREFIX,
+        std::process::id(),
+        NEXT_SCRATCH.fetch_add(1, Ordering::Relaxed)
+    );
+    admin
+        .execute(format!("create database {}", name).as_str())
+        .await
+        .expect("Could not create a scratch database");
+
+    let url = with_database(&url, &name);
+    let pool = PgPool::connect(&url)
+        .await
+        .expect("Connection failed for the scratch database");
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .expect("Could not migrate the scratch database");
+    pool.close().await;
+
+    Database::new(&url)
+        .await
+        .expect("Connection failed for the scratch database")
+}
+
+/// Address that creates the cycles and places the votes built by `cycle` and `vote`
+pub const PLACER: &str = "0x00000000000000000000000000000000000000aa";
+
+/// Builds a cycle of chain 1, created by `PLACER` at its starting block
+pub fn cycle(id: u64, starting_block: u64, block_length: u64) -> Cycle {
+    Cycle {
+        id: BigDecimal::from(id),
+        chain_id: BigDecimal::from(1),
+        block_number: BigDecimal::from(starting_block),
+        creator: PLACER.parse().unwrap(),
+        starting_block: BigDecimal::from(starting_block),
+        block_length: BigDecimal::from(block_length),
+        vote_price: BigDecimal::from(10),
+        balance: BigDecimal::default(),
+        current: false,
+    }
+}
+
+/// Builds an unclaimed vote of chain 1 placed by `PLACER`
+pub fn vote(id: u64, cycle_id: u64, symbol: &[u8; 4], amount: u64, block_number: u64) -> Vote {
+    Vote {
+        id: BigDecimal::from(id),
+        chain_id: BigDecimal::from(1),
+        block_number: BigDecimal::from(block_number),
+        cycle_id: BigDecimal::from(cycle_id),
+        placer: PLACER.parse().unwrap(),
+        symbol: *symbol,
+        amount: BigDecimal::from(amount),
+        placement: Some(BigDecimal::from(1)),
+        claimed: false,
+    }
+}
+
+/// Drops the scratch databases nobody is connected to, i.e. those of finished test runs
+async fn drop_scratch_databases(admin: &mut PgConnection) {
+    let names: Vec<String> = sqlx::query_scalar(
+        "
+select datname
+from pg_database
+where datname like $1
+and datname not in (
+    select datname
+    from pg_stat_activity
+    where datname is not null
+)
+        ",
+    )
+    .bind(format!("{}%", SCRATCH_PREFIX))
+    .fetch_all(&mut *admin)
+    .await
+    .expect("Could not list scratch databases");
+
+    for name in names {
+        // another run may have dropped it in the meantime
+        let _ = admin
+            .execute(format!("drop database if exists {}", name).as_str())
+            .await;
+    }
+}
+
+/// Replaces the database name in a Postgres connection `url`
+fn with_database(url: &str, name: &str) -> String {
+    let (url, params) = match url.split_once('?') {
+        Some((url, params)) => (url, Some(params)),
+        None => (url, None),
+    };
+    let server = match url.rsplit_once('/') {
+        Some((server, _)) if server.ends_with('/') => url,
+        Some((server, _)) => server,
+        None => url,
+    };
+
+    match params {
+        Some(params) => format!("{}/{}?{}", server, name, params),
+        None => format!("{}/{}", server, name),
+    }
+}
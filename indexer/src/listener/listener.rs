@@ -1,11 +1,13 @@
+use std::fmt;
+use std::num::NonZeroU64;
 use std::sync::Arc;
 use std::time::Duration;
 
 use bigdecimal::{BigDecimal, FromPrimitive};
 use bytes::{bigdecimal_to_bytes, bytes_to_bigdecimal};
-use database::{Cycle, Database, Vote};
+use database::{Claim, Cycle, Database, DatabaseError, Vote};
 use ethers::{
-    contract::abigen,
+    contract::{abigen, ContractError},
     providers::{Middleware, Provider, StreamExt, Ws},
     types::{H160, U64},
 };
@@ -21,6 +23,40 @@ abigen!(
 
 type RacerContract = Racer<Provider<Ws>>;
 
+/// Errors that stop a block range from being indexed
+#[derive(Debug)]
+enum IndexError {
+    /// The contract's events could not be queried from the RPC
+    Rpc(ContractError<Provider<Ws>>),
+    Database(DatabaseError),
+}
+
+impl IndexError {
+    /// Whether indexing the range may succeed if it is attempted again
+    fn is_retryable(&self) -> bool {
+        match self {
+            // the RPC is expected to recover, and the range is queried again from the same block
+            Self::Rpc(_) => true,
+            Self::Database(e) => e.is_retryable(),
+        }
+    }
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rpc(e) => write!(f, "could not query events: {}", e),
+            Self::Database(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<DatabaseError> for IndexError {
+    fn from(error: DatabaseError) -> Self {
+        Self::Database(error)
+    }
+}
+
 pub struct Listener {
     contract_address: String,
     starting_block: u64,
@@ -28,6 +64,8 @@ pub struct Listener {
     database: Database,
     reorg_threshold: BigDecimal,
     chain_id: BigDecimal,
+    // when set, leaderboards are snapshotted every this many blocks instead of at every vote
+    snapshot_interval: Option<NonZeroU64>,
 }
 
 impl Listener {
@@ -39,6 +77,7 @@ impl Listener {
             database,
             reorg_threshold: BigDecimal::from(7),
             chain_id: BigDecimal::from(1),
+            snapshot_interval: None,
         }
     }
 
@@ -63,6 +102,11 @@ impl Listener {
         self
     }
 
+    pub fn with_snapshot_interval(mut self, interval: NonZeroU64) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
     /// Starts listening to the provider
     pub async fn start(mut self) {
         let provider = Provider::<Ws>::connect(self.rpc_url.clone())
@@ -84,6 +128,23 @@ impl Listener {
 
     /// Watches for new blocks and triggers indexing
     async fn listen_blocks(&self, client: Arc<Provider<Ws>>, contract: &RacerContract) {
+        // votes indexed before placements were recorded are indexed again from the earliest one,
+        // which also refreshes the statistics of their players
+        let mut backfill_height = match self
+            .database
+            .get_backfill_height(self.chain_id.clone())
+            .await
+        {
+            Ok(backfill_height) => backfill_height,
+            Err(e) => {
+                tracing::error!("could not get backfill height: {}", e);
+                return;
+            }
+        };
+        if let Some(backfill_height) = &backfill_height {
+            tracing::info!("backfilling votes from block {}", backfill_height);
+        }
+
         loop {
             let mut stream = client.watch_blocks().await.unwrap();
 
@@ -94,22 +155,56 @@ impl Listener {
                 let Some(block_number) = block.number else { return };
                 tracing::trace!("found block number: {}", block_number.to_string());
 
-                let Ok(current_height) = self
+                let current_height = match self
                     .database
                     .get_block_height(self.chain_id.clone())
-                    .await else { return };
+                    .await
+                {
+                    Ok(current_height) => current_height,
+                    Err(e) if e.is_retryable() => {
+                        tracing::warn!("could not get block height, retrying next block: {}", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::error!("could not get block height: {}", e);
+                        return;
+                    }
+                };
 
                 let reorg_height = bytes_to_bigdecimal(block_number) - &self.reorg_threshold;
 
                 // this picks which block to index from
                 // step 1 - finds the max of either last indexed block or configured START_HEIGHT
                 // step 2 - finds the min of the previous value or current RPC height - 7
+                // step 3 - goes back to the earliest vote left to backfill, if any
                 let useful_height =
                     BigDecimal::max(current_height, BigDecimal::from(self.starting_block));
                 let useful_height = BigDecimal::min(useful_height, reorg_height.clone());
+                let useful_height = match &backfill_height {
+                    Some(backfill_height) => {
+                        BigDecimal::min(useful_height, backfill_height.clone())
+                    }
+                    None => useful_height,
+                };
+                let from_block = match bigdecimal_to_bytes(useful_height) {
+                    Ok(from_block) => from_block,
+                    Err(e) => {
+                        tracing::error!("invalid block height to index from: {}", e);
+                        return;
+                    }
+                };
 
-                self.index(contract, bigdecimal_to_bytes(useful_height.clone()))
-                    .await;
+                match self.index(contract, from_block, reorg_height.clone()).await {
+                    Ok(_) => backfill_height = None,
+                    Err(e) if e.is_retryable() => {
+                        tracing::warn!("could not index block range, retrying next block: {}", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::error!("could not index block range: {}", e);
+                        return;
+                    }
+                }
 
                 match self
                     .database
@@ -123,155 +218,157 @@ impl Listener {
         }
     }
 
-    /// Given a block start, it saves all the events from that block to the database
-    async fn index(&self, contract: &RacerContract, from_block: U64) {
+    /// Given a block start, it saves all the events from that block to the database and finalizes
+    /// cycles that ended at or before `confirmed_block`
+    async fn index(
+        &self,
+        contract: &RacerContract,
+        from_block: U64,
+        confirmed_block: BigDecimal,
+    ) -> Result<(), IndexError> {
         tracing::trace!("indexing from block {}", from_block.to_string());
         let events = contract
             .events()
             .from_block(from_block)
             .query_with_meta()
-            .await;
-
-        if let Ok(events) = events {
-            let Ok(mut tx) = self.database.start_transaction().await else { return };
-
-            let result = self
-                .database
-                .delete_cycles(
-                    &mut tx,
-                    bytes_to_bigdecimal(from_block),
-                    self.chain_id.clone(),
-                )
-                .await;
-
-            match result {
-                Ok(_) => tracing::info!("removed stale cycles"),
-                Err(e) => tracing::error!("error removing stale cycles: {:?}", e),
+            .await
+            .map_err(IndexError::Rpc)?;
+
+        let mut tx = self.database.start_transaction().await?;
+
+        // group events so each kind is written with a single statement
+        let mut cycles = Vec::new();
+        let mut votes = Vec::new();
+        let mut claims = Vec::new();
+        for (event, metadata) in events {
+            match event {
+                RacerEvents::CycleCreatedFilter(event) => {
+                    cycles.push(self.cycle_from_event(event, metadata.block_number))
+                }
+                RacerEvents::VotePlacedFilter(event) => {
+                    votes.push(self.vote_from_event(event, metadata.block_number))
+                }
+                RacerEvents::VoteClaimedFilter(event) => {
+                    claims.push(self.claim_from_event(event, metadata.block_number))
+                }
             }
+        }
 
-            let result = self
-                .database
-                .delete_votes(
-                    &mut tx,
-                    bytes_to_bigdecimal(from_block),
-                    self.chain_id.clone(),
-                )
-                .await;
-
-            match result {
-                Ok(_) => tracing::info!("removed stale votes"),
-                Err(e) => tracing::error!("error removing stale votes: {:?}", e),
-            }
+        // remember what was indexed so rows that disappeared can be told apart
+        let cycle_ids: Vec<_> = cycles.iter().map(|cycle| cycle.id.clone()).collect();
+        let vote_ids: Vec<_> = votes.iter().map(|vote| vote.id.clone()).collect();
+        let claim_ids: Vec<_> = claims.iter().map(|claim| claim.vote_id.clone()).collect();
 
-            let result = self
-                .database
-                .reset_vote_claims(
-                    &mut tx,
-                    bytes_to_bigdecimal(from_block),
-                    self.chain_id.clone(),
-                )
-                .await;
-
-            match result {
-                Ok(_) => tracing::info!("removed stale vote claims"),
-                Err(e) => tracing::error!("error removing stale vote claims: {:?}", e),
-            }
+        let count = cycles.len();
+        self.database.create_cycles(&mut tx, cycles).await?;
+        tracing::info!("{} cycles saved to db", count);
 
-            for (event, metadata) in events {
-                match event {
-                    RacerEvents::CycleCreatedFilter(event) => {
-                        self.create_cycle(&mut tx, event, metadata.block_number)
-                            .await
-                    }
-                    RacerEvents::VotePlacedFilter(event) => {
-                        self.create_vote(&mut tx, event, metadata.block_number)
-                            .await
-                    }
-                    RacerEvents::VoteClaimedFilter(event) => self.claim_vote(&mut tx, event).await,
-                }
-            }
+        let count = votes.len();
+        self.database.create_votes(&mut tx, votes).await?;
+        tracing::info!("{} votes saved to db", count);
 
-            match tx.commit().await {
-                Ok(_) => tracing::info!("indexed from block {}", from_block.to_string()),
-                Err(e) => tracing::error!("could not commit transaction: {:?}", e),
-            }
-        }
-    }
+        let count = claims.len();
+        self.database.claim_votes(&mut tx, claims).await?;
+        tracing::info!("{} vote claims saved to db", count);
 
-    /// Saves a cycle to the database
-    async fn create_cycle(
-        &self,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-        event: CycleCreatedFilter,
-        block_number: U64,
-    ) {
-        let result = self
+        let reorg = self
             .database
-            .create_cycle(
-                tx,
-                Cycle {
-                    id: bytes_to_bigdecimal(event.id),
-                    chain_id: self.chain_id.clone(),
-                    block_number: bytes_to_bigdecimal(block_number),
-                    creator: format!("{:#032x}", event.creator),
-                    starting_block: bytes_to_bigdecimal(event.p2),
-                    block_length: bytes_to_bigdecimal(event.p3),
-                    vote_price: bytes_to_bigdecimal(event.p4),
-                    balance: BigDecimal::default(),
-                    current: false,
-                },
+            .prune_orphans(
+                &mut tx,
+                bytes_to_bigdecimal(from_block),
+                self.chain_id.clone(),
+                &cycle_ids,
+                &vote_ids,
+                &claim_ids,
             )
-            .await;
-
-        match result {
-            Ok(_) => tracing::info!("cycle saved to db: {:?}", event),
-            Err(e) => tracing::error!("error saving cycle to db: {:?}", e),
+            .await?;
+
+        if let Some(reorg) = &reorg {
+            tracing::warn!(
+                "reorg {} orphaned rows between blocks {} and {}",
+                reorg.id,
+                reorg.from_block,
+                reorg.to_block
+            );
         }
-    }
 
-    /// Saves a vote to the database
-    async fn create_vote(
-        &self,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-        event: VotePlacedFilter,
-        block_number: U64,
-    ) {
-        let result = self
-            .database
-            .create_vote(
-                tx,
-                Vote {
-                    id: bytes_to_bigdecimal(event.vote_id),
-                    chain_id: self.chain_id.clone(),
-                    block_number: bytes_to_bigdecimal(block_number),
-                    cycle_id: bytes_to_bigdecimal(event.cycle_id),
-                    placer: format!("{:#032x}", event.placer),
-                    symbol: event.symbol,
-                    amount: bytes_to_bigdecimal(event.amount),
-                },
+        let reorg_id = reorg.map(|reorg| reorg.id);
+
+        self.database
+            .refresh_players(
+                &mut tx,
+                bytes_to_bigdecimal(from_block),
+                self.chain_id.clone(),
+                reorg_id,
             )
-            .await;
+            .await?;
+        tracing::info!("refreshed player statistics");
+
+        self.database
+            .record_snapshots(
+                &mut tx,
+                bytes_to_bigdecimal(from_block),
+                confirmed_block.clone(),
+                self.chain_id.clone(),
+                self.snapshot_interval,
+            )
+            .await?;
+        tracing::info!("recorded leaderboard snapshots");
 
-        match result {
-            Ok(_) => tracing::info!("vote saved to db: {:?}", event),
-            Err(e) => tracing::error!("error saving vote to db: {:?}", e),
+        let finalized = self
+            .database
+            .finalize_cycles(&mut tx, confirmed_block, self.chain_id.clone(), reorg_id)
+            .await?;
+        if finalized > 0 {
+            tracing::info!("finalized {} cycles", finalized);
         }
+
+        tx.commit().await.map_err(DatabaseError::from)?;
+        tracing::info!("indexed from block {}", from_block.to_string());
+
+        Ok(())
     }
 
-    /// Saves a vote claim to the database
-    async fn claim_vote(
-        &self,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-        event: VoteClaimedFilter,
-    ) {
-        let result = self
-            .database
-            .claim_vote(tx, bytes_to_bigdecimal(event.id), self.chain_id.clone())
-            .await;
+    /// Converts a `CycleCreated` event into a cycle row
+    fn cycle_from_event(&self, event: CycleCreatedFilter, block_number: U64) -> Cycle {
+        tracing::trace!("found cycle: {:?}", event);
+        Cycle {
+            id: bytes_to_bigdecimal(event.id),
+            chain_id: self.chain_id.clone(),
+            block_number: bytes_to_bigdecimal(block_number),
+            creator: event.creator.into(),
+            starting_block: bytes_to_bigdecimal(event.p2),
+            block_length: bytes_to_bigdecimal(event.p3),
+            vote_price: bytes_to_bigdecimal(event.p4),
+            balance: BigDecimal::default(),
+            current: false,
+        }
+    }
+
+    /// Converts a `VoteClaimed` event into a claim
+    fn claim_from_event(&self, event: VoteClaimedFilter, block_number: U64) -> Claim {
+        tracing::trace!("found vote claim: {:?}", event);
+        Claim {
+            vote_id: bytes_to_bigdecimal(event.id),
+            chain_id: self.chain_id.clone(),
+            block_number: bytes_to_bigdecimal(block_number),
+            reward: bytes_to_bigdecimal(event.reward),
+        }
+    }
 
-        match result {
-            Ok(_) => tracing::info!("vote claim saved to db: {:?}", event),
-            Err(e) => tracing::error!("error saving vote claim to db: {:?}", e),
+    /// Converts a `VotePlaced` event into a vote row
+    fn vote_from_event(&self, event: VotePlacedFilter, block_number: U64) -> Vote {
+        tracing::trace!("found vote: {:?}", event);
+        Vote {
+            id: bytes_to_bigdecimal(event.vote_id),
+            chain_id: self.chain_id.clone(),
+            block_number: bytes_to_bigdecimal(block_number),
+            cycle_id: bytes_to_bigdecimal(event.cycle_id),
+            placer: event.placer.into(),
+            symbol: event.symbol,
+            amount: bytes_to_bigdecimal(event.amount),
+            placement: Some(bytes_to_bigdecimal(event.placement)),
+            claimed: false,
         }
     }
 }
@@ -1,6 +1,7 @@
 mod listener;
 
 use std::env;
+use std::num::NonZeroU64;
 
 use database::Database;
 use dotenvy::dotenv;
@@ -26,8 +27,18 @@ async fn main() {
         .await
         .expect("Connection failed for DATABASE_URL");
 
-    // start indexing blockchain events
-    Listener::new(database)
+    // start indexing blockchain events, snapshotting leaderboards every `SNAPSHOT_INTERVAL` blocks
+    // when it is set to a positive number
+    let mut listener = Listener::new(database);
+    if let Some(interval) = env::var("SNAPSHOT_INTERVAL")
+        .ok()
+        .filter(|interval| !interval.is_empty())
+    {
+        let interval: NonZeroU64 = interval.parse().expect("Invalid SNAPSHOT_INTERVAL");
+        listener = listener.with_snapshot_interval(interval);
+    }
+
+    listener
         .with_rpc_url(env::var("RPC_URL").expect("RPC_URL is not set"))
         .with_starting_block(
             env::var("START_HEIGHT")
@@ -0,0 +1,99 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::DatabaseError;
+use serde_json::json;
+
+use crate::auth::AuthError;
+
+/// Errors returned by API handlers, rendered as `{"error": {"code", "message"}}` bodies
+#[derive(Debug)]
+pub enum ApiError {
+    /// The requested resource does not exist
+    NotFound(String),
+    /// A path or query parameter is malformed
+    BadRequest(String),
+    /// The sign-in attempt or session token was rejected
+    Unauthorized(String),
+    /// The database is temporarily unreachable and the request may be retried
+    Unavailable,
+    /// Anything else, details are logged rather than returned
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Unavailable => "unavailable",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::NotFound(message) | Self::BadRequest(message) | Self::Unauthorized(message) => {
+                message.clone()
+            }
+            Self::Unavailable => "service temporarily unavailable".to_string(),
+            Self::Internal(_) => "internal server error".to_string(),
+        }
+    }
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(error: DatabaseError) -> Self {
+        match error {
+            DatabaseError::NotFound => Self::NotFound("resource not found".to_string()),
+            error if error.is_retryable() => Self::Unavailable,
+            error => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> Self {
+        Self::Unauthorized(error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let Self::Internal(error) = &self {
+            tracing::error!("api request failed: {}", error);
+        }
+
+        let body = json!({
+            "error": {
+                "code": self.code(),
+                "message": self.message(),
+            }
+        });
+        (self.status(), Json(body)).into_response()
+    }
+}
@@ -0,0 +1,420 @@
+pub mod error;
+pub mod models;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{FromRequestParts, Path, Query, State, TypedHeader};
+use axum::headers::authorization::{Authorization, Bearer};
+use axum::http::request::Parts;
+use axum::routing::{get, post};
+use axum::{async_trait, Json, Router};
+use bigdecimal::BigDecimal;
+use bytes::Address;
+use database::{Cursor, Database, DatabaseError};
+use serde::Deserialize;
+
+use crate::auth::{Auth, Session};
+
+use error::ApiError;
+use models::{
+    block, uint, ChainStatusResponse, CycleResponse, CycleResultResponse, LeaderboardEntryResponse,
+    NonceResponse, PageResponse, VoteResponse,
+};
+
+/// Page size used when a request does not set `limit`
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub database: Database,
+    pub chain_id: BigDecimal,
+    pub auth: Arc<Auth>,
+}
+
+/// Builds the versioned HTTP API, meant to be nested under `/v1`
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/cycles", get(list_cycles))
+        .route("/cycles/:id", get(get_cycle))
+        .route("/cycles/:id/leaderboard", get(get_cycle_leaderboard))
+        .route("/players/:address/votes", get(list_player_votes))
+        .route("/chain/status", get(get_chain_status))
+        .route("/auth/nonce", get(get_nonce))
+        .route("/auth/verify", post(verify_sign_in))
+        .route("/auth/session", get(get_session))
+        .fallback(|| async { ApiError::NotFound("route not found".to_string()) })
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct PageParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+impl PageParams {
+    fn cursor(&self) -> Result<Option<Cursor>, ApiError> {
+        self.cursor
+            .as_deref()
+            .map(Cursor::from_str)
+            .transpose()
+            .map_err(|e| ApiError::BadRequest(e.to_string()))
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE)
+    }
+}
+
+/// Parses a `uint256` id from the path
+fn parse_id(id: &str) -> Result<BigDecimal, ApiError> {
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ApiError::BadRequest(format!("invalid id `{}`", id)));
+    }
+    BigDecimal::from_str(id).map_err(|_| ApiError::BadRequest(format!("invalid id `{}`", id)))
+}
+
+/// Lists cycles, newest starting block first
+async fn list_cycles(
+    State(state): State<ApiState>,
+    params: Result<Query<PageParams>, QueryRejection>,
+) -> Result<Json<PageResponse<CycleResponse>>, ApiError> {
+    let Query(params) = params?;
+    let page = state
+        .database
+        .list_cycles(state.chain_id, params.cursor()?, params.limit())
+        .await?;
+
+    Ok(Json(PageResponse::try_from_page(
+        page,
+        CycleResponse::try_from,
+    )?))
+}
+
+/// Gets a cycle along with its final result once it has been finalized
+async fn get_cycle(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<CycleResponse>, ApiError> {
+    let id = parse_id(&id)?;
+    let cycle = state
+        .database
+        .get_cycle(id.clone(), state.chain_id.clone())
+        .await
+        .map_err(|e| not_found(e, "cycle"))?;
+
+    let result = match state.database.get_cycle_result(id, state.chain_id).await {
+        Ok(result) => Some(CycleResultResponse::try_from(result)?),
+        Err(DatabaseError::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut cycle = CycleResponse::try_from(cycle)?;
+    cycle.result = result;
+    Ok(Json(cycle))
+}
+
+/// Gets a cycle's symbol totals, largest first
+async fn get_cycle_leaderboard(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<LeaderboardEntryResponse>>, ApiError> {
+    let id = parse_id(&id)?;
+
+    // an unknown cycle is a 404 rather than an empty leaderboard
+    state
+        .database
+        .get_cycle(id.clone(), state.chain_id.clone())
+        .await
+        .map_err(|e| not_found(e, "cycle"))?;
+
+    let leaderboard = state.database.get_leaderboard(id, state.chain_id).await?;
+    let leaderboard = leaderboard
+        .into_iter()
+        .map(LeaderboardEntryResponse::try_from)
+        .collect::<Result<_, _>>()?;
+
+    Ok(Json(leaderboard))
+}
+
+/// Lists the votes placed by a player, newest first
+async fn list_player_votes(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+    params: Result<Query<PageParams>, QueryRejection>,
+) -> Result<Json<PageResponse<VoteResponse>>, ApiError> {
+    let Query(params) = params?;
+    let address = Address::from_str(&address)
+        .map_err(|e| ApiError::BadRequest(format!("invalid address `{}`: {}", address, e)))?;
+    let page = state
+        .database
+        .list_votes_by_placer(address, state.chain_id, params.cursor()?, params.limit())
+        .await?;
+
+    Ok(Json(PageResponse::try_from_page(
+        page,
+        VoteResponse::try_from,
+    )?))
+}
+
+/// Reports how far the indexer has progressed and which cycle is running
+async fn get_chain_status(
+    State(state): State<ApiState>,
+) -> Result<Json<ChainStatusResponse>, ApiError> {
+    let indexed_block = state
+        .database
+        .get_block_height(state.chain_id.clone())
+        .await?;
+    let current_cycle = match state
+        .database
+        .get_current_cycle(state.chain_id.clone())
+        .await
+    {
+        Ok(cycle) => Some(uint(&cycle.id)?),
+        Err(DatabaseError::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(Json(ChainStatusResponse {
+        chain_id: uint(&state.chain_id)?,
+        indexed_block: block(&indexed_block)?,
+        current_cycle,
+    }))
+}
+
+/// Issues a nonce to include in a Sign-In with Ethereum message
+async fn get_nonce(State(state): State<ApiState>) -> Json<NonceResponse> {
+    Json(NonceResponse {
+        nonce: state.auth.issue_nonce(),
+    })
+}
+
+#[derive(Deserialize)]
+struct SignInRequest {
+    message: String,
+    signature: String,
+}
+
+/// Verifies a signed EIP-4361 message and returns a session token for its address
+async fn verify_sign_in(
+    State(state): State<ApiState>,
+    request: Result<Json<SignInRequest>, JsonRejection>,
+) -> Result<Json<Session>, ApiError> {
+    let Json(request) = request?;
+    let session = state.auth.sign_in(&request.message, &request.signature)?;
+    tracing::debug!("{} signed in", session.address);
+
+    Ok(Json(session))
+}
+
+/// Returns the session of the `Authorization: Bearer` token
+async fn get_session(Authenticated(session): Authenticated) -> Json<Session> {
+    Json(session)
+}
+
+/// Extracts the session of a request's `Authorization: Bearer` token
+struct Authenticated(Session);
+
+#[async_trait]
+impl FromRequestParts<ApiState> for Authenticated {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ApiState) -> Result<Self, ApiError> {
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| ApiError::Unauthorized("missing bearer token".to_string()))?;
+
+        Ok(Self(state.auth.verify_token(bearer.token())?))
+    }
+}
+
+/// Names the missing resource in a not found error
+fn not_found(error: DatabaseError, resource: &str) -> ApiError {
+    match error {
+        DatabaseError::NotFound => ApiError::NotFound(format!("{} not found", resource)),
+        error => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use database::testing::{cycle, setup_db, vote, PLACER};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Builds the API over a fresh database holding three cycles, with two votes in the first
+    async fn state() -> ApiState {
+        let database = setup_db().await;
+
+        let mut tx = database.start_transaction().await.unwrap();
+        database
+            .create_cycles(
+                &mut tx,
+                vec![cycle(1, 100, 10), cycle(2, 200, 10), cycle(3, 300, 10)],
+            )
+            .await
+            .unwrap();
+        database
+            .create_votes(
+                &mut tx,
+                vec![
+                    vote(1, 1, "🔥".as_bytes().try_into().unwrap(), 2, 101),
+                    vote(2, 1, "🚀".as_bytes().try_into().unwrap(), 5, 102),
+                ],
+            )
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        ApiState {
+            database,
+            chain_id: BigDecimal::from(1),
+            auth: Arc::new(Auth::new("localhost:3000", 1, "secret")),
+        }
+    }
+
+    /// Sends `request` through the router and returns the response status and JSON body
+    async fn send(state: &ApiState, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn get(state: &ApiState, uri: &str) -> (StatusCode, Value) {
+        send(state, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    fn ids(page: &Value) -> Vec<&str> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_through_cycles_and_votes() {
+        let state = state().await;
+
+        let (status, page) = get(&state, "/cycles?limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["3", "2"]);
+        assert_eq!(page["next_cursor"], "200_2");
+
+        let (status, page) = get(&state, "/cycles?limit=2&cursor=200_2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["1"]);
+        assert_eq!(page["next_cursor"], Value::Null);
+
+        let uri = format!("/players/{}/votes?limit=1", PLACER);
+        let (status, page) = get(&state, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page), ["2"]);
+        assert_eq!(page["items"][0]["emoji"], "🚀");
+        assert_eq!(page["items"][0]["placement"], "1");
+
+        let cursor = page["next_cursor"].as_str().unwrap();
+        let (_, page) = get(&state, &format!("{}&cursor={}", uri, cursor)).await;
+        assert_eq!(ids(&page), ["1"]);
+        assert_eq!(page["next_cursor"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn gets_cycles_with_their_leaderboards() {
+        let state = state().await;
+
+        let (status, cycle) = get(&state, "/cycles/1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cycle["id"], "1");
+        assert_eq!(cycle["starting_block"], 100);
+        // not finalized yet
+        assert!(cycle.get("result").is_none());
+
+        let (status, leaderboard) = get(&state, "/cycles/1/leaderboard").await;
+        assert_eq!(status, StatusCode::OK);
+        let entries: Vec<_> = leaderboard
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["emoji"].as_str().unwrap(),
+                    entry["amount"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(entries, [("🚀", "5"), ("🔥", "2")]);
+
+        // a known cycle without votes has an empty leaderboard
+        let (status, leaderboard) = get(&state, "/cycles/2/leaderboard").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(leaderboard, json!([]));
+    }
+
+    #[tokio::test]
+    async fn maps_errors_to_responses() {
+        let state = state().await;
+
+        let cases = [
+            ("/cycles/abc", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/cycles/-1", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/cycles?cursor=abc", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/cycles?limit=abc", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/players/abc/votes", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/cycles/9", StatusCode::NOT_FOUND, "not_found"),
+            ("/cycles/9/leaderboard", StatusCode::NOT_FOUND, "not_found"),
+            ("/unknown", StatusCode::NOT_FOUND, "not_found"),
+            ("/auth/session", StatusCode::UNAUTHORIZED, "unauthorized"),
+        ];
+        for (uri, expected_status, expected_code) in cases {
+            let (status, body) = get(&state, uri).await;
+            assert_eq!(status, expected_status, "{}", uri);
+            assert_eq!(body["error"]["code"], expected_code, "{}", uri);
+            assert!(body["error"]["message"].is_string(), "{}", uri);
+        }
+
+        let (_, body) = get(&state, "/cycles/9").await;
+        assert_eq!(body["error"]["message"], "cycle not found");
+
+        let request = Request::get("/auth/session")
+            .header(header::AUTHORIZATION, "Bearer invalid")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["code"], "unauthorized");
+
+        let request = Request::post("/auth/verify")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        let (status, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn reports_chain_status() {
+        let state = state().await;
+        state
+            .database
+            .set_block_height(BigDecimal::from(1), BigDecimal::from(150))
+            .await
+            .unwrap();
+
+        let (status, body) = get(&state, "/chain/status").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["chain_id"], "1");
+        assert_eq!(body["indexed_block"], 150);
+    }
+}
@@ -0,0 +1,182 @@
+use bigdecimal::BigDecimal;
+use bytes::{Address, Symbol, TokenAmount, TryFromBigDecimal};
+use database::Page;
+use ethers::types::{U256, U64};
+use serde::Serialize;
+
+use super::error::ApiError;
+
+/// A page of items and the cursor to pass as `?cursor=` for the next page
+#[derive(Serialize)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    // `null` when this is the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> PageResponse<T> {
+    /// Converts every row of a database page, failing if any row cannot be represented
+    pub fn try_from_page<R>(
+        page: Page<R>,
+        convert: impl Fn(R) -> Result<T, ApiError>,
+    ) -> Result<Self, ApiError> {
+        Ok(Self {
+            items: page
+                .items
+                .into_iter()
+                .map(convert)
+                .collect::<Result<_, _>>()?,
+            next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CycleResponse {
+    pub id: String,
+    pub block_number: u64,
+    pub creator: Address,
+    pub starting_block: u64,
+    pub block_length: u64,
+    pub vote_price: TokenAmount,
+    pub balance: TokenAmount,
+    pub current: bool,
+    // present once the cycle has ended and been finalized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<CycleResultResponse>,
+}
+
+impl TryFrom<database::Cycle> for CycleResponse {
+    type Error = ApiError;
+
+    fn try_from(cycle: database::Cycle) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: uint(&cycle.id)?,
+            block_number: block(&cycle.block_number)?,
+            creator: cycle.creator,
+            starting_block: block(&cycle.starting_block)?,
+            block_length: block(&cycle.block_length)?,
+            vote_price: amount(&cycle.vote_price)?,
+            balance: amount(&cycle.balance)?,
+            current: cycle.current,
+            result: None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CycleResultResponse {
+    // `null` when no votes were placed in the cycle
+    pub winning_symbol: Option<String>,
+    pub total_pot: TokenAmount,
+    pub winning_votes: i64,
+    pub finalized_block: u64,
+}
+
+impl TryFrom<database::CycleResult> for CycleResultResponse {
+    type Error = ApiError;
+
+    fn try_from(result: database::CycleResult) -> Result<Self, Self::Error> {
+        Ok(Self {
+            winning_symbol: result
+                .winning_symbol
+                .map(|symbol| Symbol::from(symbol).to_string()),
+            total_pot: amount(&result.total_pot)?,
+            winning_votes: result.winning_votes,
+            finalized_block: block(&result.finalized_block)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardEntryResponse {
+    pub emoji: String,
+    // raw `bytes4` symbol as stored on chain
+    pub symbol: String,
+    pub amount: String,
+}
+
+impl TryFrom<database::models::Leaderboard> for LeaderboardEntryResponse {
+    type Error = ApiError;
+
+    fn try_from(entry: database::models::Leaderboard) -> Result<Self, Self::Error> {
+        let symbol = Symbol::from_slice(&entry.symbol);
+        Ok(Self {
+            emoji: symbol.to_string(),
+            symbol: hex(symbol.as_bytes()),
+            amount: uint(&entry.amount.unwrap_or_default())?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct VoteResponse {
+    pub id: String,
+    pub block_number: u64,
+    pub cycle_id: String,
+    pub placer: Address,
+    pub emoji: String,
+    pub symbol: String,
+    pub amount: String,
+    // `null` until votes indexed before placements were recorded are backfilled
+    pub placement: Option<String>,
+    pub claimed: bool,
+}
+
+impl TryFrom<database::Vote> for VoteResponse {
+    type Error = ApiError;
+
+    fn try_from(vote: database::Vote) -> Result<Self, Self::Error> {
+        let symbol = Symbol::from(vote.symbol);
+        Ok(Self {
+            id: uint(&vote.id)?,
+            block_number: block(&vote.block_number)?,
+            cycle_id: uint(&vote.cycle_id)?,
+            placer: vote.placer,
+            emoji: symbol.to_string(),
+            symbol: hex(symbol.as_bytes()),
+            amount: uint(&vote.amount)?,
+            placement: vote.placement.as_ref().map(uint).transpose()?,
+            claimed: vote.claimed,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChainStatusResponse {
+    pub chain_id: String,
+    // last block the indexer has confirmed, past the reorg threshold
+    pub indexed_block: u64,
+    // `null` when no cycle is running
+    pub current_cycle: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NonceResponse {
+    pub nonce: String,
+}
+
+/// Formats a `uint256` column as a decimal string, since it may not fit in a JSON number
+pub fn uint(value: &BigDecimal) -> Result<String, ApiError> {
+    U256::try_from_bigdecimal(value)
+        .map(|value| value.to_string())
+        .map_err(|e| ApiError::Internal(format!("invalid uint256 {}: {}", value, e)))
+}
+
+/// Reads a block number column
+pub fn block(value: &BigDecimal) -> Result<u64, ApiError> {
+    U64::try_from_bigdecimal(value)
+        .map(|value| value.as_u64())
+        .map_err(|e| ApiError::Internal(format!("invalid block number {}: {}", value, e)))
+}
+
+fn amount(value: &BigDecimal) -> Result<TokenAmount, ApiError> {
+    TokenAmount::try_from_bigdecimal(value)
+        .map_err(|e| ApiError::Internal(format!("invalid token amount {}: {}", value, e)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold("0x".to_string(), |hex, byte| format!("{}{:02x}", hex, byte))
+}
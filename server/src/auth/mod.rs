@@ -0,0 +1,304 @@
+pub mod siwe;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Address;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
+use sha2::Sha256;
+
+use siwe::{verify_signature, SiweError, SiweMessage};
+
+/// How long an issued nonce can be used to sign in
+const NONCE_TTL: Duration = Duration::from_secs(5 * 60);
+/// Most nonces waiting to be used, the oldest is dropped past this
+const MAX_PENDING_NONCES: usize = 10_000;
+/// How long a session lasts when the sign-in message has no expiration time
+const SESSION_TTL: chrono::Duration = chrono::Duration::hours(24);
+
+/// Returned when a sign-in attempt or a session token is rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// The sign-in message or its signature is invalid
+    Siwe(SiweError),
+    /// The message's nonce was not issued by this server, has expired or was already used
+    InvalidNonce,
+    /// The session token is malformed or was not signed by this server
+    InvalidToken,
+    /// The session token has expired
+    ExpiredToken,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Siwe(error) => error.fmt(f),
+            Self::InvalidNonce => write!(f, "nonce is unknown, expired or already used"),
+            Self::InvalidToken => write!(f, "session token is invalid"),
+            Self::ExpiredToken => write!(f, "session token has expired"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<SiweError> for AuthError {
+    fn from(error: SiweError) -> Self {
+        Self::Siwe(error)
+    }
+}
+
+/// A verified address and the token that proves it in later requests
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub address: Address,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Issues sign-in nonces, verifies Sign-In with Ethereum messages and signs session tokens
+pub struct Auth {
+    // the `host[:port]` clients must sign in to
+    domain: String,
+    chain_id: u64,
+    // key for session token signatures
+    secret: Vec<u8>,
+    // nonces waiting to be used and when they were issued, known only to this process so a
+    // sign-in has to reach the instance that issued its nonce
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl Auth {
+    pub fn new(domain: impl Into<String>, chain_id: u64, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            domain: domain.into(),
+            chain_id,
+            secret: secret.into(),
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a single use nonce to include in a sign-in message
+    pub fn issue_nonce(&self) -> String {
+        let nonce = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let now = Instant::now();
+
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, issued_at| now.duration_since(*issued_at) < NONCE_TTL);
+        if nonces.len() >= MAX_PENDING_NONCES {
+            if let Some(oldest) = nonces
+                .iter()
+                .min_by_key(|(_, issued_at)| **issued_at)
+                .map(|(nonce, _)| nonce.clone())
+            {
+                nonces.remove(&oldest);
+            }
+        }
+        nonces.insert(nonce.clone(), now);
+
+        nonce
+    }
+
+    /// Verifies a signed EIP-4361 message and starts a session for its address
+    pub fn sign_in(&self, message: &str, signature: &str) -> Result<Session, AuthError> {
+        let parsed: SiweMessage = message.parse()?;
+        let now = Utc::now();
+        parsed.validate(&self.domain, self.chain_id, now)?;
+        verify_signature(message, signature, parsed.address)?;
+
+        // the nonce is only spent once everything else checks out
+        let issued_at = self.nonces.lock().unwrap().remove(&parsed.nonce);
+        if !matches!(issued_at, Some(issued_at) if issued_at.elapsed() < NONCE_TTL) {
+            return Err(AuthError::InvalidNonce);
+        }
+
+        let expires_at = parsed
+            .expiration_time
+            .map_or(now + SESSION_TTL, |time| time.min(now + SESSION_TTL));
+        Ok(self.session(parsed.address, expires_at))
+    }
+
+    /// Checks a token returned by [`Auth::sign_in`] and returns its session
+    pub fn verify_token(&self, token: &str) -> Result<Session, AuthError> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(address), Some(expires_at), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthError::InvalidToken);
+        };
+
+        let signature = hex::decode(signature).map_err(|_| AuthError::InvalidToken)?;
+        self.mac(address, expires_at)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::InvalidToken)?;
+
+        let address = address.parse().map_err(|_| AuthError::InvalidToken)?;
+        let expires_at = expires_at
+            .parse()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .ok_or(AuthError::InvalidToken)?;
+        if Utc::now() >= expires_at {
+            return Err(AuthError::ExpiredToken);
+        }
+
+        Ok(Session {
+            address,
+            token: token.to_owned(),
+            expires_at,
+        })
+    }
+
+    /// Signs a `{address}.{expires_at}.{hmac}` token for the session
+    fn session(&self, address: Address, expires_at: DateTime<Utc>) -> Session {
+        let address_hex = address.to_lowercase_hex();
+        let expires_at_seconds = expires_at.timestamp().to_string();
+        let signature = self.mac(&address_hex, &expires_at_seconds).finalize();
+        let token = format!(
+            "{}.{}.{}",
+            address_hex,
+            expires_at_seconds,
+            hex::encode(signature.into_bytes())
+        );
+
+        Session {
+            address,
+            token,
+            // the token only keeps whole seconds
+            expires_at: Utc.timestamp_opt(expires_at.timestamp(), 0).unwrap(),
+        }
+    }
+
+    fn mac(&self, address: &str, expires_at: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key length");
+        mac.update(address.as_bytes());
+        mac.update(b".");
+        mac.update(expires_at.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::utils::hash_message;
+
+    use super::*;
+
+    const DOMAIN: &str = "localhost:3000";
+    const CHAIN_ID: u64 = 1;
+
+    fn message(address: Address, domain: &str, chain_id: u64, nonce: &str) -> String {
+        format!(
+            "{} wants you to sign in with your Ethereum account:\n\
+             {}\n\
+             \n\
+             Sign in to Racer\n\
+             \n\
+             URI: http://{}\n\
+             Version: 1\n\
+             Chain ID: {}\n\
+             Nonce: {}\n\
+             Issued At: {}",
+            domain,
+            address.to_checksum(),
+            domain,
+            chain_id,
+            nonce,
+            Utc::now().to_rfc3339(),
+        )
+    }
+
+    fn sign(wallet: &LocalWallet, message: &str) -> String {
+        wallet.sign_hash(hash_message(message)).to_string()
+    }
+
+    #[test]
+    fn signs_in_with_a_generated_key() {
+        let auth = Auth::new(DOMAIN, CHAIN_ID, "secret");
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let address = Address::from(wallet.address());
+
+        let message = message(address, DOMAIN, CHAIN_ID, &auth.issue_nonce());
+        let session = auth.sign_in(&message, &sign(&wallet, &message)).unwrap();
+        assert_eq!(session.address, address);
+
+        let verified = auth.verify_token(&session.token).unwrap();
+        assert_eq!(verified.address, address);
+        assert_eq!(verified.expires_at, session.expires_at);
+
+        // a nonce can only be used once
+        assert_eq!(
+            auth.sign_in(&message, &sign(&wallet, &message))
+                .unwrap_err(),
+            AuthError::InvalidNonce
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_sign_ins() {
+        let auth = Auth::new(DOMAIN, CHAIN_ID, "secret");
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let other = LocalWallet::new(&mut rand::thread_rng());
+        let address = Address::from(wallet.address());
+
+        let signed = message(address, DOMAIN, CHAIN_ID, &auth.issue_nonce());
+        assert_eq!(
+            auth.sign_in(&signed, &sign(&other, &signed)).unwrap_err(),
+            AuthError::Siwe(SiweError::InvalidSignature)
+        );
+
+        let unknown_nonce = message(address, DOMAIN, CHAIN_ID, "abcdefgh12345678");
+        assert_eq!(
+            auth.sign_in(&unknown_nonce, &sign(&wallet, &unknown_nonce))
+                .unwrap_err(),
+            AuthError::InvalidNonce
+        );
+
+        let other_domain = message(address, "example.com", CHAIN_ID, &auth.issue_nonce());
+        assert_eq!(
+            auth.sign_in(&other_domain, &sign(&wallet, &other_domain))
+                .unwrap_err(),
+            AuthError::Siwe(SiweError::DomainMismatch)
+        );
+
+        let other_chain = message(address, DOMAIN, 5, &auth.issue_nonce());
+        assert_eq!(
+            auth.sign_in(&other_chain, &sign(&wallet, &other_chain))
+                .unwrap_err(),
+            AuthError::Siwe(SiweError::ChainMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let auth = Auth::new(DOMAIN, CHAIN_ID, "secret");
+        let address = Address::from(LocalWallet::new(&mut rand::thread_rng()).address());
+        let session = auth.session(address, Utc::now() + SESSION_TTL);
+
+        let other = Address::from(LocalWallet::new(&mut rand::thread_rng()).address());
+        let (_, rest) = session.token.split_once('.').unwrap();
+        let forged = format!("{}.{}", other.to_lowercase_hex(), rest);
+        assert_eq!(
+            auth.verify_token(&forged).unwrap_err(),
+            AuthError::InvalidToken
+        );
+
+        let other_secret = Auth::new(DOMAIN, CHAIN_ID, "another secret");
+        assert_eq!(
+            other_secret.verify_token(&session.token).unwrap_err(),
+            AuthError::InvalidToken
+        );
+
+        let expired = auth.session(address, Utc::now() - chrono::Duration::seconds(1));
+        assert_eq!(
+            auth.verify_token(&expired.token).unwrap_err(),
+            AuthError::ExpiredToken
+        );
+    }
+}
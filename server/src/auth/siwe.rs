@@ -0,0 +1,196 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::Address;
+use chrono::{DateTime, Utc};
+use ethers::types::{Signature, H160};
+
+const PREAMBLE_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// A Sign-In with Ethereum message as defined by EIP-4361
+#[derive(Debug, PartialEq, Eq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+/// Returned when a sign-in message is malformed or does not hold for this server
+#[derive(Debug, PartialEq, Eq)]
+pub enum SiweError {
+    /// The message does not follow the EIP-4361 format
+    Malformed(String),
+    /// The message is for another domain
+    DomainMismatch,
+    /// The message is for another chain
+    ChainMismatch,
+    /// The message has expired or is not valid yet
+    OutsideValidity,
+    /// The signature was not made by the message's address
+    InvalidSignature,
+}
+
+impl fmt::Display for SiweError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(reason) => write!(f, "malformed sign-in message: {}", reason),
+            Self::DomainMismatch => write!(f, "sign-in message is for another domain"),
+            Self::ChainMismatch => write!(f, "sign-in message is for another chain"),
+            Self::OutsideValidity => write!(f, "sign-in message is expired or not valid yet"),
+            Self::InvalidSignature => write!(f, "signature does not match the address"),
+        }
+    }
+}
+
+impl std::error::Error for SiweError {}
+
+fn malformed(reason: impl Into<String>) -> SiweError {
+    SiweError::Malformed(reason.into())
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, SiweError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| malformed(format!("invalid timestamp `{}`", value)))
+}
+
+impl SiweMessage {
+    /// Checks that the message was issued for `domain` and `chain_id` and is valid at `now`
+    pub fn validate(
+        &self,
+        domain: &str,
+        chain_id: u64,
+        now: DateTime<Utc>,
+    ) -> Result<(), SiweError> {
+        if self.domain != domain {
+            return Err(SiweError::DomainMismatch);
+        }
+        if self.chain_id != chain_id {
+            return Err(SiweError::ChainMismatch);
+        }
+        let expired = self.expiration_time.is_some_and(|time| now >= time);
+        let premature = self.not_before.is_some_and(|time| now < time);
+        if expired || premature {
+            return Err(SiweError::OutsideValidity);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that `signature` is an EIP-191 personal signature of `message` by `address`
+pub fn verify_signature(message: &str, signature: &str, address: Address) -> Result<(), SiweError> {
+    let signature = Signature::from_str(signature.trim_start_matches("0x"))
+        .map_err(|_| SiweError::InvalidSignature)?;
+    signature
+        .verify(message, H160::from(address))
+        .map_err(|_| SiweError::InvalidSignature)
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| malformed("missing preamble"))?
+            .to_owned();
+
+        // EIP-4361 requires the address in its EIP-55 checksummed form
+        let line = lines.next().ok_or_else(|| malformed("missing address"))?;
+        let address: Address = line
+            .parse()
+            .map_err(|e| malformed(format!("invalid address: {}", e)))?;
+        if address.to_checksum() != line {
+            return Err(malformed("address must be checksummed"));
+        }
+
+        if lines.next() != Some("") {
+            return Err(malformed("expected a blank line after the address"));
+        }
+
+        // the statement is optional and followed by a blank line when present
+        let mut statement = None;
+        if lines.peek().is_some_and(|line| !line.starts_with("URI: ")) {
+            statement = lines.next().map(str::to_owned);
+            if lines.next() != Some("") {
+                return Err(malformed("expected a blank line after the statement"));
+            }
+        }
+
+        // fields appear in a fixed order, optional ones may be skipped
+        let mut field = |name: &str| {
+            let prefix = format!("{}: ", name);
+            let value = lines.peek()?.strip_prefix(&prefix)?.to_owned();
+            lines.next();
+            Some(value)
+        };
+        let required = |value: Option<String>, name: &str| {
+            value.ok_or_else(|| malformed(format!("missing `{}`", name)))
+        };
+
+        let uri = required(field("URI"), "URI")?;
+        let version = required(field("Version"), "Version")?;
+        if version != "1" {
+            return Err(malformed(format!("unsupported version `{}`", version)));
+        }
+        let chain_id = required(field("Chain ID"), "Chain ID")?
+            .parse()
+            .map_err(|_| malformed("invalid chain id"))?;
+        let nonce = required(field("Nonce"), "Nonce")?;
+        if nonce.len() < 8 || !nonce.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(malformed(
+                "nonce must be at least 8 alphanumeric characters",
+            ));
+        }
+        let issued_at = parse_time(&required(field("Issued At"), "Issued At")?)?;
+        let expiration_time = field("Expiration Time")
+            .map(|time| parse_time(&time))
+            .transpose()?;
+        let not_before = field("Not Before")
+            .map(|time| parse_time(&time))
+            .transpose()?;
+        let request_id = field("Request ID");
+
+        let mut resources = Vec::new();
+        if lines.peek() == Some(&"Resources:") {
+            lines.next();
+            while let Some(resource) = lines.peek().and_then(|line| line.strip_prefix("- ")) {
+                resources.push(resource.to_owned());
+                lines.next();
+            }
+        }
+
+        if let Some(line) = lines.next() {
+            return Err(malformed(format!("unexpected line `{}`", line)));
+        }
+
+        Ok(Self {
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
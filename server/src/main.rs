@@ -1,17 +1,26 @@
+mod api;
+mod auth;
 mod ws;
 
 use std::env;
 use std::panic;
+use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc};
 
 use axum::{routing::get, Router};
+use bytes::bytes_to_bigdecimal;
+use database::Database;
 use dotenvy::dotenv;
+use ethers::providers::{Http, Middleware, Provider};
+use rand::RngCore;
 use tokio::task::JoinSet;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::api::ApiState;
+use crate::auth::Auth;
 use crate::ws::publishers::run_publishers;
-use crate::ws::{websocket_handler, PubSubState};
+use crate::ws::{websocket_handler, FanOut, Heartbeat, PubSubState};
 
 #[tokio::main]
 async fn main() {
@@ -31,13 +40,95 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // create a database connection instance, routing reads to a replica when one is configured
+    let mut database = Database::new(&env::var("DATABASE_URL").expect("DATABASE_URL is not set"))
+        .await
+        .expect("Connection failed for DATABASE_URL");
+    if let Some(replica_url) = env::var("DATABASE_REPLICA_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+    {
+        database = database
+            .with_replica(&replica_url)
+            .await
+            .expect("Connection failed for DATABASE_REPLICA_URL");
+    }
+    if let Some(max_lag) = env::var("DATABASE_REPLICA_MAX_LAG")
+        .ok()
+        .filter(|lag| !lag.is_empty())
+    {
+        database = database
+            .with_max_replica_lag(max_lag.parse().expect("Invalid DATABASE_REPLICA_MAX_LAG"));
+    }
+
+    // the chain served by the API, read once from the RPC
+    let rpc_url = env::var("RPC_URL").expect("RPC_URL is not set");
+    let chain_id = Provider::<Http>::try_from(rpc_url.as_str())
+        .expect("Could not connect to RPC")
+        .get_chainid()
+        .await
+        .expect("Could not get chain id from RPC");
+
+    // sign-in messages must name this domain, and session tokens are signed with the secret
+    let domain = env::var("AUTH_DOMAIN").unwrap_or("localhost:3000".to_string());
+    let secret = match env::var("AUTH_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+    {
+        Some(secret) => secret.into_bytes(),
+        None => {
+            tracing::warn!("AUTH_SECRET is not set, session tokens will not survive a restart");
+            let mut secret = vec![0; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret
+        }
+    };
+    let auth = Arc::new(Auth::new(domain, chain_id.as_u64(), secret));
+
+    let chain_id = bytes_to_bigdecimal(chain_id);
+    let api_state = ApiState {
+        database: database.clone(),
+        chain_id: chain_id.clone(),
+        auth: auth.clone(),
+    };
+
+    // how often websocket clients are pinged and how long they have to answer or to subscribe,
+    // in seconds
+    let mut heartbeat = Heartbeat::default();
+    for (name, duration) in [
+        ("WS_PING_INTERVAL", &mut heartbeat.ping_interval),
+        ("WS_PING_TIMEOUT", &mut heartbeat.ping_timeout),
+        ("WS_IDLE_TIMEOUT", &mut heartbeat.idle_timeout),
+    ] {
+        if let Some(seconds) = env::var(name).ok().filter(|seconds| !seconds.is_empty()) {
+            let seconds = seconds
+                .parse()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .unwrap_or_else(|| panic!("Invalid {}", name));
+            *duration = Duration::from_secs(seconds);
+        }
+    }
+
+    // how broadcasts reach the websocket clients of other server instances
+    let fanout = match env::var("WS_FANOUT").ok().filter(|name| !name.is_empty()) {
+        Some(name) => FanOut::from_name(&name).expect("Invalid WS_FANOUT"),
+        None => FanOut::InProcess,
+    };
+    tracing::info!("websocket fan-out: {:?}", fanout);
+
     // create global state for web server
-    let state = Arc::new(PubSubState::default());
+    let state = Arc::new(
+        PubSubState::new(database.clone(), chain_id, auth)
+            .with_heartbeat(heartbeat)
+            .with_fanout(fanout),
+    );
 
     // define application routes
     let app = Router::new()
         .route("/ws", get(websocket_handler))
         .with_state(state.clone())
+        .nest("/v1", api::router(api_state))
         // enable tracing for all tower http requests
         .layer(
             TraceLayer::new_for_http()
@@ -49,12 +140,7 @@ async fn main() {
 
     // run the websocket publishers
     set.spawn(async move {
-        run_publishers(
-            state,
-            &env::var("DATABASE_URL").expect("DATABASE_URL is not set"),
-            &env::var("RPC_URL").expect("RPC_URL is not set"),
-        )
-        .await;
+        run_publishers(state, database, &rpc_url).await;
     });
 
     // run the server
@@ -0,0 +1,434 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use database::{AdvisoryLock, Database, DatabaseError};
+use futures::{stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use super::leaderboard::LeaderboardMessage;
+use super::publishers::Leaderboard;
+use super::topics::LeaderboardTopic;
+use super::PubSubState;
+
+/// Channel the elected publisher notifies with the topic of each leaderboard it publishes
+const LEADERBOARD_CHANNEL: &str = "leaderboards";
+
+/// Session advisory lock held by the instance elected to publish leaderboards
+const PUBLISHER_LOCK: i64 = 0x7261_6365_7200;
+
+/// How often instances share their online count and topics, and the elected instance publishes
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long an instance counts as running after it last shared its online count
+const INSTANCE_TTL: Duration = Duration::from_secs(15);
+
+/// How long a leaderboard may take to compute before it is skipped until the next round
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most leaderboards the elected publisher computes at once
+const PUBLISH_CONCURRENCY: usize = 8;
+
+/// Most topics the elected publisher publishes for the whole fleet, the topics followed by the
+/// fewest instances are left out past it
+const MAX_FLEET_TOPICS: usize = 200;
+
+/// How websocket broadcasts reach the sockets of every server instance
+#[derive(Clone, Debug)]
+pub enum FanOut {
+    /// Each instance computes its own leaderboards and counts only its own sockets
+    InProcess,
+    /// One elected instance computes the leaderboards of every instance's topics, which reach
+    /// the instances through Postgres `LISTEN`/`NOTIFY`, and online counts are summed over the
+    /// instances
+    Postgres { instance_id: String },
+}
+
+impl FanOut {
+    /// Reads a backend by name, `in_process` or `postgres`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "in_process" => Some(Self::InProcess),
+            "postgres" => Some(Self::Postgres {
+                instance_id: format!("{:016x}", rand::random::<u64>()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Publishes the leaderboards of every instance's topics while this instance holds the
+/// publisher lock, and keeps trying to take the lock otherwise
+///
+/// The lock is a Postgres session lock, so it is released when the elected instance stops or
+/// loses its connection and another instance takes over on its next attempt.
+pub async fn run_elected_publisher(leaderboard: Leaderboard, database: Database) {
+    let mut interval = time::interval(ANNOUNCE_INTERVAL);
+    let mut lock: Option<AdvisoryLock> = None;
+    // the leaderboards published by this instance, so unchanged ones are not published again
+    let mut published: HashMap<LeaderboardTopic, LeaderboardMessage> = HashMap::new();
+    // the topics whose published leaderboard is final, so they are not computed again
+    let mut settled: HashSet<LeaderboardTopic> = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        if elect(&database, &mut lock).await {
+            published.clear();
+            settled.clear();
+        }
+        if lock.is_none() {
+            continue;
+        }
+
+        let topics = match fleet_topics(&database).await {
+            Ok(topics) => topics,
+            Err(e) => {
+                tracing::error!("failed to list the topics of server instances: {}", e);
+                continue;
+            }
+        };
+        published.retain(|topic, _| topics.contains(topic));
+        settled.retain(|topic| topics.contains(topic));
+
+        // leaderboards are computed concurrently and each is published once it is ready
+        let pending: Vec<_> = topics.difference(&settled).copied().collect();
+        let leaderboard = &leaderboard;
+        let mut builds = stream::iter(pending)
+            .map(|topic| async move { (topic, build(leaderboard, topic).await) })
+            .buffer_unordered(PUBLISH_CONCURRENCY);
+
+        while let Some((topic, built)) = builds.next().await {
+            let Some((message, is_settled)) = built else { continue };
+            if published.get(&topic) == Some(&message) {
+                if is_settled {
+                    settled.insert(topic);
+                }
+                continue;
+            }
+
+            let result = match serde_json::to_string(&message) {
+                Ok(json) => {
+                    database
+                        .publish_leaderboard(LEADERBOARD_CHANNEL, &topic.to_string(), &json)
+                        .await
+                }
+                Err(e) => {
+                    tracing::error!("failed to serialize leaderboard: {}", e);
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => {
+                    published.insert(topic, message);
+                    if is_settled {
+                        settled.insert(topic);
+                    }
+                }
+                Err(e) => tracing::error!("failed to publish leaderboard {:?}: {}", topic, e),
+            }
+        }
+
+        // leaderboards and instances nobody needs any more
+        let topics: Vec<String> = topics.iter().map(ToString::to_string).collect();
+        if let Err(e) = database.prune_published_leaderboards(&topics).await {
+            tracing::error!("failed to prune published leaderboards: {}", e);
+        }
+        if let Err(e) = database.prune_server_instances(INSTANCE_TTL).await {
+            tracing::error!("failed to prune server instances: {}", e);
+        }
+    }
+}
+
+/// Takes the publisher lock if no instance holds it, and drops it once its connection is lost
+///
+/// Returns whether this instance was just elected.
+async fn elect(database: &Database, lock: &mut Option<AdvisoryLock>) -> bool {
+    if let Some(held) = lock {
+        if held.is_held().await {
+            return false;
+        }
+        tracing::warn!("lost the leaderboard publisher lock");
+        *lock = None;
+    }
+
+    match database.try_advisory_lock(PUBLISHER_LOCK).await {
+        Ok(Some(held)) => {
+            tracing::info!("elected to publish leaderboards");
+            *lock = Some(held);
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            tracing::error!("failed to take the leaderboard publisher lock: {}", e);
+            false
+        }
+    }
+}
+
+/// Computes a topic's leaderboard and whether it is final, or returns `None` when it could not be
+/// computed in time
+async fn build(
+    leaderboard: &Leaderboard,
+    topic: LeaderboardTopic,
+) -> Option<(LeaderboardMessage, bool)> {
+    tracing::trace!("publishing leaderboard {:?} to every instance", topic);
+    // checked first, so the leaderboard published next is the final one
+    let is_settled = leaderboard.is_settled(topic).await;
+    match time::timeout(PUBLISH_TIMEOUT, leaderboard.build_leaderboard(topic)).await {
+        Ok(message) => message.map(|message| (message, is_settled)),
+        Err(_) => {
+            tracing::error!("failed to publish leaderboard {:?}", topic);
+            None
+        }
+    }
+}
+
+/// Shares this instance's online count and topics with the other instances, and publishes the
+/// leaderboards of the elected instance to the local subscribers
+pub async fn run_replica(state: Arc<PubSubState>, database: Database, instance_id: String) {
+    let (sender, mut notifications) = mpsc::channel(100);
+    let listener = tokio::spawn(listen(database.clone(), sender));
+    let mut interval = time::interval(ANNOUNCE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => announce(&state, &database, &instance_id).await,
+            // the elected instance learns of new topics from the announcement, and leaderboards
+            // it already publishes are read straight away
+            _ = state.leaderboards.created() => {
+                announce(&state, &database, &instance_id).await;
+                refresh_all(&state, &database).await;
+            }
+            notification = notifications.recv() => match notification {
+                Some(Some(topic)) => refresh(&state, &database, topic).await,
+                // the listener reconnected and may have missed notifications
+                Some(None) => refresh_all(&state, &database).await,
+                None => break,
+            },
+        }
+    }
+
+    listener.abort();
+}
+
+/// Forwards the topics the elected instance notifies about, or `None` whenever notifications may
+/// have been missed
+async fn listen(database: Database, sender: mpsc::Sender<Option<LeaderboardTopic>>) {
+    loop {
+        let mut listener = match database.listen(LEADERBOARD_CHANNEL).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("failed to listen for leaderboards: {}", e);
+                time::sleep(ANNOUNCE_INTERVAL).await;
+                continue;
+            }
+        };
+        if sender.send(None).await.is_err() {
+            return;
+        }
+
+        loop {
+            let notification = match listener.recv().await {
+                Ok(Some(topic)) => match topic.parse() {
+                    Ok(topic) => Some(topic),
+                    Err(e) => {
+                        tracing::warn!("ignoring leaderboard notification: {}", e);
+                        continue;
+                    }
+                },
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::error!("failed to receive leaderboard notification: {}", e);
+                    break;
+                }
+            };
+            if sender.send(notification).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Records this instance's online count and topics, and updates the count sent to clients with
+/// the sum over every running instance
+async fn announce(state: &PubSubState, database: &Database, instance_id: &str) {
+    let online = state.online.load(Ordering::Relaxed);
+    let topics: Vec<String> = state
+        .leaderboards
+        .topics()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    if let Err(e) = database
+        .record_server_instance(instance_id, online.try_into().unwrap_or(i32::MAX), &topics)
+        .await
+    {
+        tracing::error!("failed to record server instance: {}", e);
+        return;
+    }
+
+    match database.list_server_instances(INSTANCE_TTL).await {
+        Ok(instances) => {
+            let total: i64 = instances
+                .iter()
+                .map(|instance| i64::from(instance.online))
+                .sum();
+            state
+                .online_total
+                .store(total.try_into().unwrap_or(u32::MAX), Ordering::Relaxed);
+        }
+        Err(e) => tracing::error!("failed to list server instances: {}", e),
+    }
+}
+
+/// Lists the topics any running instance has subscribers for, up to `MAX_FLEET_TOPICS` of those
+/// followed by the most instances
+async fn fleet_topics(database: &Database) -> Result<HashSet<LeaderboardTopic>, DatabaseError> {
+    let instances = database.list_server_instances(INSTANCE_TTL).await?;
+    let mut followers: HashMap<LeaderboardTopic, usize> = HashMap::new();
+    for topic in instances
+        .iter()
+        .flat_map(|instance| &instance.topics)
+        .filter_map(|topic| topic.parse().ok())
+    {
+        *followers.entry(topic).or_default() += 1;
+    }
+
+    if followers.len() > MAX_FLEET_TOPICS {
+        tracing::warn!(
+            "{} leaderboard topics have subscribers, publishing {}",
+            followers.len(),
+            MAX_FLEET_TOPICS
+        );
+    }
+    let mut topics: Vec<_> = followers.into_iter().collect();
+    topics.sort_unstable_by_key(|&(topic, followers)| (Reverse(followers), topic));
+    Ok(topics
+        .into_iter()
+        .take(MAX_FLEET_TOPICS)
+        .map(|(topic, _)| topic)
+        .collect())
+}
+
+/// Publishes the leaderboard the elected instance last published for a topic, if the topic has
+/// subscribers here
+async fn refresh(state: &PubSubState, database: &Database, topic: LeaderboardTopic) {
+    if !state.leaderboards.topics().contains(&topic) {
+        return;
+    }
+
+    let message = match database.get_published_leaderboard(&topic.to_string()).await {
+        Ok(message) => message,
+        Err(DatabaseError::NotFound) => return,
+        Err(e) => {
+            tracing::error!("failed to read published leaderboard {:?}: {}", topic, e);
+            return;
+        }
+    };
+    match serde_json::from_str(&message) {
+        Ok(leaderboard) => state.leaderboards.publish(topic, leaderboard),
+        Err(e) => tracing::error!("invalid published leaderboard {:?}: {}", topic, e),
+    }
+}
+
+async fn refresh_all(state: &PubSubState, database: &Database) {
+    for topic in state.leaderboards.topics() {
+        refresh(state, database, topic).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::testing::setup_db;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::ws::testing::{leaderboard, state, topic};
+
+    #[tokio::test]
+    async fn elects_one_publisher_at_a_time() {
+        let database = setup_db().await;
+        let (mut first, mut second) = (None, None);
+
+        assert!(elect(&database, &mut first).await);
+        assert!(!elect(&database, &mut second).await);
+        assert!(second.is_none());
+        // the elected instance keeps its lock without being elected again
+        assert!(!elect(&database, &mut first).await);
+        assert!(first.is_some());
+
+        // another instance takes over once the lock's session ends
+        drop(first);
+        for _ in 0..50 {
+            if elect(&database, &mut second).await {
+                break;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(second.is_some());
+    }
+
+    #[tokio::test]
+    async fn announces_online_counts_and_topics_to_the_fleet() {
+        let state = Arc::new(state().await);
+        let database = state.database.clone();
+        state.online.store(3, Ordering::Relaxed);
+        let _subscriptions = [
+            state.leaderboards.subscribe(topic(4)).unwrap(),
+            state.leaderboards.subscribe(topic(5)).unwrap(),
+        ];
+        let topics = ["1:4".to_owned(), "1:6".to_owned(), "invalid".to_owned()];
+        database
+            .record_server_instance("other", 2, &topics)
+            .await
+            .unwrap();
+
+        announce(&state, &database, "this").await;
+        assert_eq!(state.online_total.load(Ordering::Relaxed), 5);
+        let topics = fleet_topics(&database).await.unwrap();
+        assert_eq!(topics, HashSet::from([topic(4), topic(5), topic(6)]));
+
+        // past the cap, the topics followed by fewer instances are left out
+        let topics: Vec<_> = (0..MAX_FLEET_TOPICS as u64)
+            .map(|cycle_id| topic(100 + cycle_id).to_string())
+            .collect();
+        database
+            .record_server_instance("busy", 0, &topics)
+            .await
+            .unwrap();
+        let topics = fleet_topics(&database).await.unwrap();
+        assert_eq!(topics.len(), MAX_FLEET_TOPICS);
+        assert!(topics.contains(&topic(4)));
+    }
+
+    #[tokio::test]
+    async fn refreshes_topics_from_the_published_leaderboards() {
+        let state = Arc::new(state().await);
+        let database = state.database.clone();
+        let subscription = state.leaderboards.subscribe(topic(4)).unwrap();
+
+        // nothing is published for the topic yet
+        refresh(&state, &database, topic(4)).await;
+        assert!(subscription.snapshot().is_none());
+
+        let json = serde_json::to_string(&leaderboard(4, 7)).unwrap();
+        for cycle_id in [4, 5] {
+            database
+                .publish_leaderboard(LEADERBOARD_CHANNEL, &topic(cycle_id).to_string(), &json)
+                .await
+                .unwrap();
+        }
+        refresh_all(&state, &database).await;
+        let snapshot = subscription.snapshot().unwrap();
+        let snapshot: Value = serde_json::from_str(&snapshot.message).unwrap();
+        assert_eq!(snapshot["metadata"]["votes"], 7);
+
+        // topics without subscribers here are not created
+        refresh(&state, &database, topic(5)).await;
+        assert_eq!(state.leaderboards.topics(), vec![topic(4)]);
+    }
+}
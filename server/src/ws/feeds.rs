@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bigdecimal::BigDecimal;
+use bytes::Address;
+use database::Database;
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tokio::time;
+
+use super::players::{my_claims_message, my_votes_message};
+
+/// How often a player feed checks the database for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A per-player stream of messages
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PlayerFeed {
+    MyVotes(Address),
+    MyClaims(Address),
+}
+
+struct Feed {
+    // holds the latest message, `None` until the first poll
+    receiver: watch::Receiver<Option<String>>,
+    poll: AbortHandle,
+    subscribers: usize,
+}
+
+/// Database polls for the player feeds that have subscribers
+///
+/// A feed is polled once however many sockets follow it. Its poll starts with the first
+/// subscription and stops when the last subscription is dropped.
+pub struct PlayerFeeds {
+    feeds: Mutex<HashMap<PlayerFeed, Feed>>,
+    database: Database,
+    chain_id: BigDecimal,
+}
+
+/// A subscriber's receiver for a feed, which leaves the feed when dropped
+pub struct FeedSubscription {
+    pub receiver: watch::Receiver<Option<String>>,
+    feed: PlayerFeed,
+    feeds: Arc<PlayerFeeds>,
+}
+
+impl PlayerFeeds {
+    pub fn new(database: Database, chain_id: BigDecimal) -> Self {
+        Self {
+            feeds: Mutex::new(HashMap::new()),
+            database,
+            chain_id,
+        }
+    }
+
+    /// Joins a feed, starting its poll if needed
+    pub fn subscribe(self: &Arc<Self>, feed: PlayerFeed) -> FeedSubscription {
+        let mut feeds = self.feeds.lock().unwrap();
+        let entry = feeds.entry(feed).or_insert_with(|| {
+            tracing::debug!("starting player feed {:?}", feed);
+            let (sender, receiver) = watch::channel(None);
+            let poll = tokio::spawn(poll(
+                feed,
+                sender,
+                self.database.clone(),
+                self.chain_id.clone(),
+            ));
+            Feed {
+                receiver,
+                poll: poll.abort_handle(),
+                subscribers: 0,
+            }
+        });
+
+        entry.subscribers += 1;
+        FeedSubscription {
+            receiver: entry.receiver.clone(),
+            feed,
+            feeds: self.clone(),
+        }
+    }
+
+    fn unsubscribe(&self, feed: PlayerFeed) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let Some(entry) = feeds.get_mut(&feed) else { return };
+
+        entry.subscribers -= 1;
+        if entry.subscribers == 0 {
+            tracing::debug!("stopping player feed {:?}", feed);
+            entry.poll.abort();
+            feeds.remove(&feed);
+        }
+    }
+}
+
+impl Drop for FeedSubscription {
+    fn drop(&mut self) {
+        self.feeds.unsubscribe(self.feed);
+    }
+}
+
+/// Builds a feed's message every `POLL_INTERVAL` and hands it to the subscribers when it changed
+async fn poll(
+    feed: PlayerFeed,
+    sender: watch::Sender<Option<String>>,
+    database: Database,
+    chain_id: BigDecimal,
+) {
+    let mut interval = time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let message = match feed {
+            PlayerFeed::MyVotes(address) => my_votes_message(&database, &chain_id, address).await,
+            PlayerFeed::MyClaims(address) => my_claims_message(&database, &chain_id, address).await,
+        };
+        match message {
+            Ok(message) => {
+                sender.send_if_modified(|current| {
+                    let changed = current.as_ref() != Some(&message);
+                    if changed {
+                        *current = Some(message);
+                    }
+                    changed
+                });
+            }
+            Err(error) => tracing::error!("failed to build player message: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::testing::setup_db;
+
+    use super::*;
+
+    const PLAYER: &str = "0x00000000000000000000000000000000000000aa";
+
+    #[tokio::test]
+    async fn shares_one_poll_per_feed() {
+        let feeds = Arc::new(PlayerFeeds::new(setup_db().await, BigDecimal::from(1)));
+        let address: Address = PLAYER.parse().unwrap();
+
+        let mut first = feeds.subscribe(PlayerFeed::MyClaims(address));
+        let mut second = feeds.subscribe(PlayerFeed::MyClaims(address));
+        let votes = feeds.subscribe(PlayerFeed::MyVotes(address));
+        assert_eq!(feeds.feeds.lock().unwrap().len(), 2);
+
+        // both subscribers see the single poll's first message
+        first.receiver.changed().await.unwrap();
+        second.receiver.changed().await.unwrap();
+        let message = first.receiver.borrow().clone().unwrap();
+        assert!(message.contains(r#""type":"my_claims""#));
+        assert_eq!(second.receiver.borrow().as_ref(), Some(&message));
+
+        drop(first);
+        assert_eq!(feeds.feeds.lock().unwrap().len(), 2);
+        drop(second);
+        drop(votes);
+        assert!(feeds.feeds.lock().unwrap().is_empty());
+    }
+}
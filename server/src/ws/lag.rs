@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+/// How many times a socket's subscriptions may fall behind within [`LAG_WINDOW`] before the
+/// socket is closed
+const MAX_LAGS: usize = 3;
+
+const LAG_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tracks how often a socket's subscriptions fall behind their channels, so clients that cannot
+/// keep up are disconnected instead of resyncing forever
+#[derive(Default)]
+pub struct LagMonitor {
+    // when each recent lag happened, oldest first
+    lags: Mutex<VecDeque<Instant>>,
+    persistent: Notify,
+}
+
+impl LagMonitor {
+    /// Records that a subscription fell behind, returning true once the socket lags persistently
+    pub fn record(&self) -> bool {
+        self.record_at(Instant::now())
+    }
+
+    fn record_at(&self, now: Instant) -> bool {
+        let mut lags = self.lags.lock().unwrap();
+        while lags
+            .front()
+            .is_some_and(|lag| now.duration_since(*lag) >= LAG_WINDOW)
+        {
+            lags.pop_front();
+        }
+        lags.push_back(now);
+
+        let persistent = lags.len() >= MAX_LAGS;
+        if persistent {
+            self.persistent.notify_one();
+        }
+        persistent
+    }
+
+    /// Waits until the socket lags persistently
+    pub async fn persistent(&self) {
+        self.persistent.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_frequent_lags_are_persistent() {
+        let monitor = LagMonitor::default();
+        let start = Instant::now();
+
+        assert!(!monitor.record_at(start));
+        assert!(!monitor.record_at(start + LAG_WINDOW / 2));
+        // the first lag has left the window
+        assert!(!monitor.record_at(start + LAG_WINDOW));
+        assert!(monitor.record_at(start + LAG_WINDOW + LAG_WINDOW / 4));
+    }
+}
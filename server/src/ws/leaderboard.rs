@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use bytes::{Symbol, TokenAmount, TryFromBigDecimal};
+use database::models::Leaderboard;
+use database::Cycle;
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::symbols::SymbolDetails;
+
+/// A leaderboard topic's state, sent whole in `leaderboard` messages
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaderboardMessage {
+    pub cycle_id: i64,
+    pub chain_id: u64,
+    pub metadata: Metadata,
+    pub leaderboard: Vec<Emoji>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Emoji {
+    pub emoji: String,
+    // raw `bytes4` symbol, which tells apart symbols that are not valid emojis
+    pub symbol: String,
+    pub value: u32,
+    // omitted when the symbol is not an emoji
+    #[serde(flatten)]
+    pub details: Option<SymbolDetails>,
+}
+
+impl Emoji {
+    pub fn new(symbol: Symbol, value: u32, details: Option<SymbolDetails>) -> Self {
+        Self {
+            emoji: symbol.to_string(),
+            symbol: format!("0x{}", hex::encode(symbol.as_bytes())),
+            value,
+            details,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub blocks_remaining: u32,
+    pub votes: i64,
+    pub vote_price: TokenAmount,
+    // the cycle's pot, see `cycle_pot`
+    pub payout: TokenAmount,
+    pub balance: TokenAmount,
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    seq: u64,
+    #[serde(flatten)]
+    leaderboard: &'a LeaderboardMessage,
+}
+
+#[derive(Serialize)]
+struct Delta<'a> {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    seq: u64,
+    cycle_id: i64,
+    chain_id: u64,
+    #[serde(skip_serializing_if = "MetadataDelta::is_empty")]
+    metadata: MetadataDelta<'a>,
+    // entries that are new or whose value or details changed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changed: Vec<&'a Emoji>,
+    // `symbol`s of entries that left the leaderboard
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct MetadataDelta<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocks_remaining: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    votes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vote_price: Option<&'a TokenAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payout: Option<&'a TokenAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<&'a TokenAmount>,
+}
+
+impl MetadataDelta<'_> {
+    fn is_empty(&self) -> bool {
+        self.blocks_remaining.is_none()
+            && self.votes.is_none()
+            && self.vote_price.is_none()
+            && self.payout.is_none()
+            && self.balance.is_none()
+    }
+}
+
+/// The pot a cycle pays out to its winners: the vote price for the summed amount of every vote,
+/// as the cycle's `total_pot` is recorded when it is finalized
+pub fn cycle_pot(cycle: &Cycle, leaderboard: &[Leaderboard]) -> Result<TokenAmount, String> {
+    let vote_price = TokenAmount::try_from_bigdecimal(&cycle.vote_price)
+        .map_err(|e| format!("invalid vote price for cycle {}: {}", cycle.id, e))?;
+    let amount = leaderboard.iter().try_fold(U256::zero(), |total, entry| {
+        let amount = entry.amount.clone().unwrap_or_default();
+        let amount = U256::try_from_bigdecimal(&amount)
+            .map_err(|e| format!("invalid amount {}: {}", amount, e))?;
+        total
+            .checked_add(amount)
+            .ok_or_else(|| format!("pot of cycle {} overflowed", cycle.id))
+    })?;
+
+    vote_price
+        .checked_mul(amount)
+        .ok_or_else(|| format!("pot of cycle {} overflowed", cycle.id))
+}
+
+/// Keeps a field only when it differs from its previous value
+fn changed<T: PartialEq>(previous: T, current: T) -> Option<T> {
+    (previous != current).then_some(current)
+}
+
+impl LeaderboardMessage {
+    /// Serializes the whole leaderboard as the `leaderboard` message numbered `seq`
+    pub fn snapshot(&self, seq: u64) -> serde_json::Result<String> {
+        serde_json::to_string(&Snapshot {
+            _type: "leaderboard",
+            seq,
+            leaderboard: self,
+        })
+    }
+
+    /// Serializes what changed since `previous` as the `leaderboard_delta` message numbered
+    /// `seq`, or returns `None` when the cycle changed and only a snapshot makes sense
+    pub fn delta(&self, previous: &Self, seq: u64) -> Option<serde_json::Result<String>> {
+        if self.cycle_id != previous.cycle_id || self.chain_id != previous.chain_id {
+            return None;
+        }
+
+        let previous_entries: HashMap<&str, &Emoji> = previous
+            .leaderboard
+            .iter()
+            .map(|entry| (entry.symbol.as_str(), entry))
+            .collect();
+        let current_entries: HashMap<&str, &Emoji> = self
+            .leaderboard
+            .iter()
+            .map(|entry| (entry.symbol.as_str(), entry))
+            .collect();
+
+        let delta = Delta {
+            _type: "leaderboard_delta",
+            seq,
+            cycle_id: self.cycle_id,
+            chain_id: self.chain_id,
+            metadata: MetadataDelta {
+                blocks_remaining: changed(
+                    previous.metadata.blocks_remaining,
+                    self.metadata.blocks_remaining,
+                ),
+                votes: changed(previous.metadata.votes, self.metadata.votes),
+                vote_price: changed(&previous.metadata.vote_price, &self.metadata.vote_price),
+                payout: changed(&previous.metadata.payout, &self.metadata.payout),
+                balance: changed(&previous.metadata.balance, &self.metadata.balance),
+            },
+            changed: self
+                .leaderboard
+                .iter()
+                .filter(|entry| previous_entries.get(entry.symbol.as_str()) != Some(entry))
+                .collect(),
+            removed: previous
+                .leaderboard
+                .iter()
+                .map(|entry| entry.symbol.as_str())
+                .filter(|symbol| !current_entries.contains_key(symbol))
+                .collect(),
+        };
+        Some(serde_json::to_string(&delta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn leaderboard(cycle_id: i64, votes: i64, entries: &[(&str, u32)]) -> LeaderboardMessage {
+        LeaderboardMessage {
+            cycle_id,
+            chain_id: 1,
+            metadata: Metadata {
+                blocks_remaining: 10,
+                votes,
+                vote_price: TokenAmount::from_wei(5.into()),
+                payout: TokenAmount::from_wei((5 * votes as u64).into()),
+                balance: TokenAmount::ZERO,
+            },
+            leaderboard: entries
+                .iter()
+                .map(|(emoji, value)| Emoji::new(emoji.parse().unwrap(), *value, None))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn deltas_only_hold_changes() {
+        let previous = leaderboard(4, 3, &[("🔥", 2), ("🌞", 1)]);
+        let current = leaderboard(4, 5, &[("🔥", 2), ("🦠", 3)]);
+
+        let delta: Value =
+            serde_json::from_str(&current.delta(&previous, 8).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            delta,
+            json!({
+                "type": "leaderboard_delta",
+                "seq": 8,
+                "cycle_id": 4,
+                "chain_id": 1,
+                "metadata": { "votes": 5, "payout": "25" },
+                "changed": [{ "emoji": "🦠", "symbol": "0xf09fa6a0", "value": 3 }],
+                "removed": ["0xf09f8c9e"],
+            })
+        );
+    }
+
+    #[test]
+    fn new_cycles_need_a_snapshot() {
+        let previous = leaderboard(4, 3, &[("🔥", 3)]);
+        assert!(leaderboard(5, 0, &[]).delta(&previous, 2).is_none());
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        // leaderboards published by another server instance are read back from JSON
+        let mut message = leaderboard(4, 3, &[("🔥", 2), ("🌞", 1)]);
+        message.leaderboard[0].details = emojis::get("🔥").map(SymbolDetails::from);
+
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(serde_json::from_str::<LeaderboardMessage>(&json).unwrap() == message);
+    }
+
+    #[test]
+    fn pots_pay_the_vote_price_for_every_amount() {
+        let cycle = Cycle {
+            id: 4.into(),
+            chain_id: 1.into(),
+            block_number: 100.into(),
+            creator: "0x00000000000000000000000000000000000000aa"
+                .parse()
+                .unwrap(),
+            starting_block: 100.into(),
+            block_length: 50.into(),
+            vote_price: 5.into(),
+            balance: 0.into(),
+            current: true,
+        };
+        let totals =
+            [("🔥", Some(3)), ("🌞", Some(2)), ("🦠", None)].map(|(emoji, amount)| Leaderboard {
+                symbol: emoji.parse::<Symbol>().unwrap().as_bytes().to_vec(),
+                amount: amount.map(Into::into),
+                max_block: None,
+            });
+
+        assert_eq!(
+            cycle_pot(&cycle, &totals),
+            Ok(TokenAmount::from_wei(25.into()))
+        );
+        assert_eq!(cycle_pot(&cycle, &[]), Ok(TokenAmount::ZERO));
+    }
+}
@@ -1,22 +1,319 @@
 use std::collections::HashSet;
 
-use serde::Deserialize;
-use serde_json::Result;
+use bytes::Address;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Result, Value};
+
+use crate::auth::{Auth, AuthError, Session};
+use crate::ws::protocol::{ErrorCode, ProtocolError};
+use crate::ws::topics::LeaderboardTopic;
 
 #[derive(PartialEq, Eq, Hash, Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum SubscriptionType {
     Online,
-    Leaderboard,
+    Leaderboard {
+        // the current cycle when omitted
+        cycle_id: Option<u64>,
+        // the server's chain when omitted
+        chain_id: Option<u64>,
+    },
+    MyVotes,
+    MyClaims,
+}
+
+impl SubscriptionType {
+    /// Whether the subscription is about the player the socket signed in as
+    pub fn requires_address(&self) -> bool {
+        matches!(self, Self::MyVotes | Self::MyClaims)
+    }
+
+    /// Fills in the subscription's defaults, or returns `None` for a player subscription
+    /// without an address
+    pub fn resolve(&self, default_chain_id: u64, address: Option<Address>) -> Option<Subscription> {
+        match self {
+            Self::Online => Some(Subscription::Online),
+            Self::Leaderboard { .. } => self
+                .leaderboard_topic(default_chain_id)
+                .map(Subscription::Leaderboard),
+            Self::MyVotes => address.map(|address| Subscription::MyVotes { address }),
+            Self::MyClaims => address.map(|address| Subscription::MyClaims { address }),
+        }
+    }
+
+    /// The leaderboard topic a `leaderboard` subscription streams
+    pub fn leaderboard_topic(&self, default_chain_id: u64) -> Option<LeaderboardTopic> {
+        match self {
+            Self::Leaderboard { cycle_id, chain_id } => Some(LeaderboardTopic {
+                chain_id: chain_id.unwrap_or(default_chain_id),
+                cycle_id: *cycle_id,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A subscription with its defaults filled in, so requests for the same stream compare equal
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Subscription {
+    Online,
+    Leaderboard(LeaderboardTopic),
+    MyVotes { address: Address },
+    MyClaims { address: Address },
+}
+
+/// Proves the socket's address, either with a signed sign-in message or a session token
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AuthRequest {
+    SignIn { message: String, signature: String },
+    Token { token: String },
+}
+
+impl AuthRequest {
+    pub fn authenticate(&self, auth: &Auth) -> std::result::Result<Session, AuthError> {
+        match self {
+            Self::SignIn { message, signature } => auth.sign_in(message, signature),
+            Self::Token { token } => auth.verify_token(token),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PubSubRequest {
-    pub address: Option<String>,
-    #[serde(default)]
-    pub subscriptions: HashSet<SubscriptionType>,
+    pub address: Option<Address>,
+    pub auth: Option<AuthRequest>,
+    // added to the socket's subscriptions
+    #[serde(default, deserialize_with = "deserialize_subscriptions")]
+    pub subscriptions: Option<HashSet<SubscriptionType>>,
+    // leaderboard subscriptions to re-send in full, after a gap in their `seq`
+    #[serde(default, deserialize_with = "deserialize_subscription_list")]
+    pub resync: HashSet<SubscriptionType>,
+}
+
+fn deserialize_subscriptions<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<HashSet<SubscriptionType>>, D::Error> {
+    deserialize_subscription_list(deserializer).map(Some)
+}
+
+/// Reads subscriptions given either by name, as in `"online"`, or as an object with a `type` and
+/// parameters, as in `{"type": "leaderboard", "cycle_id": 42}`
+fn deserialize_subscription_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<HashSet<SubscriptionType>, D::Error> {
+    Vec::<Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|subscription| {
+            let subscription = match subscription {
+                Value::String(name) => json!({ "type": name }),
+                subscription => subscription,
+            };
+            SubscriptionType::deserialize(subscription).map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+impl PubSubRequest {
+    /// Uses the socket's verified address, which an explicit `address` must match, and requires
+    /// one for player subscriptions
+    pub fn bind_session(
+        &mut self,
+        session: Option<&Session>,
+    ) -> std::result::Result<(), ProtocolError> {
+        let needs_session = self
+            .subscriptions
+            .iter()
+            .flatten()
+            .any(SubscriptionType::requires_address);
+        bind_verified_address(&mut self.address, session, needs_session)
+    }
+}
+
+/// Stops the given subscriptions, or every subscription when `subscriptions` is omitted
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeRequest {
+    pub address: Option<Address>,
+    #[serde(default, deserialize_with = "deserialize_subscriptions")]
+    pub subscriptions: Option<HashSet<SubscriptionType>>,
+}
+
+impl UnsubscribeRequest {
+    /// Uses the socket's verified address when signed in, which an explicit `address` must match
+    pub fn bind_session(
+        &mut self,
+        session: Option<&Session>,
+    ) -> std::result::Result<(), ProtocolError> {
+        let needs_address = self
+            .subscriptions
+            .iter()
+            .flatten()
+            .any(SubscriptionType::requires_address);
+        bind_address(&mut self.address, session, needs_address)
+    }
+}
+
+/// A one-off read of what a subscription would send, as in `{"type": "my_votes"}`
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub address: Option<Address>,
+    #[serde(flatten)]
+    pub subscription: SubscriptionType,
 }
 
-pub fn process_subscription_message(message: impl ToString) -> Result<PubSubRequest> {
-    serde_json::from_str(&message.to_string())
+impl QueryRequest {
+    /// Uses the socket's verified address, which an explicit `address` must match, and requires
+    /// one for player queries
+    pub fn bind_session(
+        &mut self,
+        session: Option<&Session>,
+    ) -> std::result::Result<(), ProtocolError> {
+        let needs_session = self.subscription.requires_address();
+        bind_verified_address(&mut self.address, session, needs_session)
+    }
+}
+
+/// Binds the socket's verified address, failing when player data is requested by a socket that
+/// has not signed in, since an `address` alone proves nothing
+fn bind_verified_address(
+    address: &mut Option<Address>,
+    session: Option<&Session>,
+    needs_session: bool,
+) -> std::result::Result<(), ProtocolError> {
+    if needs_session && session.is_none() {
+        return Err(ProtocolError::new(
+            ErrorCode::AddressRequired,
+            "sign in to subscribe to my_votes and my_claims",
+        ));
+    }
+    bind_address(address, session, needs_session)
+}
+
+fn bind_address(
+    address: &mut Option<Address>,
+    session: Option<&Session>,
+    needs_address: bool,
+) -> std::result::Result<(), ProtocolError> {
+    if let Some(session) = session {
+        if address.is_some_and(|address| address != session.address) {
+            return Err(ProtocolError::new(
+                ErrorCode::AddressMismatch,
+                "address does not match the signed in address",
+            ));
+        }
+        *address = Some(session.address);
+    }
+
+    if needs_address && address.is_none() {
+        return Err(ProtocolError::new(
+            ErrorCode::AddressRequired,
+            "an address is required for my_votes and my_claims subscriptions",
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AuthenticatedMessage<'a> {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    #[serde(flatten)]
+    session: &'a Session,
+}
+
+#[derive(Serialize)]
+struct ResyncMessage {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    subscription: Subscription,
+    skipped: u64,
+}
+
+/// Tells the socket a subscription fell behind and skipped messages, and that the
+/// subscription's current state follows
+pub fn resync_message(subscription: Subscription, skipped: u64) -> Result<String> {
+    serde_json::to_string(&ResyncMessage {
+        _type: "resync",
+        subscription,
+        skipped,
+    })
+}
+
+/// Tells the socket which address it is signed in as and the token to resume with
+pub fn authenticated_message(session: &Session) -> Result<String> {
+    serde_json::to_string(&AuthenticatedMessage {
+        _type: "authenticated",
+        session,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    const PLAYER: &str = "0x0000000000000000000000000000000000000001";
+    const OTHER: &str = "0x0000000000000000000000000000000000000002";
+
+    fn session() -> Session {
+        Session {
+            address: PLAYER.parse().unwrap(),
+            token: String::new(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        }
+    }
+
+    fn code<T>(result: std::result::Result<T, ProtocolError>) -> Option<ErrorCode> {
+        result.err().map(|error| error.code)
+    }
+
+    #[test]
+    fn serves_player_data_to_signed_in_sockets_only() {
+        let subscribe = |payload: Value| serde_json::from_value::<PubSubRequest>(payload).unwrap();
+        let query = |payload: Value| serde_json::from_value::<QueryRequest>(payload).unwrap();
+
+        // an address alone does not prove the socket controls it
+        let mut request = subscribe(json!({ "address": PLAYER, "subscriptions": ["my_votes"] }));
+        assert_eq!(
+            code(request.bind_session(None)),
+            Some(ErrorCode::AddressRequired)
+        );
+        let mut request = query(json!({ "type": "my_claims", "address": PLAYER }));
+        assert_eq!(
+            code(request.bind_session(None)),
+            Some(ErrorCode::AddressRequired)
+        );
+
+        let mut request = subscribe(json!({ "subscriptions": ["my_votes", "online"] }));
+        request.bind_session(Some(&session())).unwrap();
+        assert_eq!(request.address, Some(PLAYER.parse().unwrap()));
+
+        let mut request = query(json!({ "type": "my_votes", "address": OTHER }));
+        assert_eq!(
+            code(request.bind_session(Some(&session()))),
+            Some(ErrorCode::AddressMismatch)
+        );
+
+        // public data needs no sign-in
+        let mut request = subscribe(json!({ "subscriptions": ["online", "leaderboard"] }));
+        request.bind_session(None).unwrap();
+        let mut request = query(json!({ "type": "online" }));
+        request.bind_session(None).unwrap();
+
+        // player subscriptions can still be stopped by address once the session expired
+        let mut request: UnsubscribeRequest =
+            serde_json::from_value(json!({ "address": PLAYER, "subscriptions": ["my_votes"] }))
+                .unwrap();
+        request.bind_session(None).unwrap();
+        let mut request: UnsubscribeRequest =
+            serde_json::from_value(json!({ "subscriptions": ["my_votes"] })).unwrap();
+        assert_eq!(
+            code(request.bind_session(None)),
+            Some(ErrorCode::AddressRequired)
+        );
+    }
 }
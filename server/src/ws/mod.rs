@@ -1,9 +1,62 @@
+pub mod fanout;
+pub mod feeds;
+pub mod lag;
+pub mod leaderboard;
 pub mod message;
+pub mod players;
+pub mod protocol;
 pub mod subscribers;
 pub mod publishers;
+pub mod symbols;
+pub mod topics;
 pub mod websocket;
 
-use message::*;
 use subscribers::*;
 
-pub use websocket::{websocket_handler, PubSubState};
+pub use fanout::FanOut;
+pub use websocket::{websocket_handler, Heartbeat, PubSubState};
+
+/// Fixtures shared by the websocket tests
+#[cfg(test)]
+mod testing {
+    use std::sync::Arc;
+
+    use bigdecimal::BigDecimal;
+    use bytes::TokenAmount;
+    use database::testing::setup_db;
+
+    use super::leaderboard::{Emoji, LeaderboardMessage, Metadata};
+    use super::topics::LeaderboardTopic;
+    use super::PubSubState;
+    use crate::auth::Auth;
+
+    /// Builds the websocket state over a fresh database, for chain 1
+    pub async fn state() -> PubSubState {
+        let auth = Auth::new("localhost:3000", 1, "secret");
+        PubSubState::new(setup_db().await, BigDecimal::from(1), Arc::new(auth))
+    }
+
+    /// The topic of a cycle of chain 1
+    pub fn topic(cycle_id: u64) -> LeaderboardTopic {
+        LeaderboardTopic {
+            chain_id: 1,
+            cycle_id: Some(cycle_id),
+        }
+    }
+
+    /// Builds the leaderboard of a cycle of chain 1 where 🔥 got all of the `votes`
+    pub fn leaderboard(cycle_id: i64, votes: u32) -> LeaderboardMessage {
+        LeaderboardMessage {
+            cycle_id,
+            chain_id: 1,
+            metadata: Metadata {
+                blocks_remaining: 10,
+                votes: votes.into(),
+                vote_price: TokenAmount::from_wei(5.into()),
+                payout: TokenAmount::from_wei((5 * votes).into()),
+                balance: TokenAmount::ZERO,
+            },
+            leaderboard: vec![Emoji::new("🔥".parse().unwrap(), votes, None)],
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use bigdecimal::BigDecimal;
+use bytes::{Address, Symbol, TokenAmount, TryFromBigDecimal};
+use database::{Database, DatabaseError, PlayerClaim, Vote};
+use ethers::types::U256;
+use serde::Serialize;
+
+use super::leaderboard::cycle_pot;
+
+/// Most recent winning votes included in a `my_claims` message
+const MAX_CLAIMS: i64 = 100;
+
+#[derive(Serialize)]
+struct MyVotesMessage {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    address: Address,
+    // `null` when no cycle is running
+    cycle_id: Option<String>,
+    votes: Vec<MyVote>,
+}
+
+#[derive(Serialize)]
+struct MyVote {
+    id: String,
+    emoji: String,
+    amount: String,
+    // where the vote's symbol currently ranks in the leaderboard, starting at 1
+    rank: usize,
+    // the vote's share of the pot if the cycle ended now
+    projected_reward: TokenAmount,
+}
+
+#[derive(Serialize)]
+struct MyClaimsMessage {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    address: Address,
+    claims: Vec<MyClaim>,
+}
+
+#[derive(Serialize)]
+struct MyClaim {
+    vote_id: String,
+    cycle_id: String,
+    emoji: String,
+    amount: String,
+    status: ClaimStatus,
+    reward: Option<TokenAmount>,
+    claimed_block: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ClaimStatus {
+    Claimable,
+    Claimed,
+}
+
+/// Builds the `my_votes` message for `address`: their votes in the current cycle, where each
+/// vote's symbol ranks and what it would pay out if the cycle ended now
+pub async fn my_votes_message(
+    database: &Database,
+    chain_id: &BigDecimal,
+    address: Address,
+) -> Result<String, String> {
+    let cycle = match database.get_current_cycle(chain_id.clone()).await {
+        Ok(cycle) => Some(cycle),
+        Err(DatabaseError::NotFound) => None,
+        Err(e) => return Err(format!("could not fetch current cycle: {}", e)),
+    };
+
+    let mut votes = Vec::new();
+    if let Some(cycle) = &cycle {
+        let leaderboard = database
+            .get_leaderboard(cycle.id.clone(), chain_id.clone())
+            .await
+            .map_err(|e| format!("could not fetch leaderboard: {}", e))?;
+        let player_votes = database
+            .get_player_votes(address, cycle.id.clone(), chain_id.clone())
+            .await
+            .map_err(|e| format!("could not fetch votes of {}: {}", address, e))?;
+
+        let totals: Vec<(Symbol, U256)> = leaderboard
+            .iter()
+            .map(|entry| {
+                let amount = entry.amount.clone().unwrap_or_default();
+                Ok((Symbol::from_slice(&entry.symbol), uint256(&amount)?))
+            })
+            .collect::<Result<_, String>>()?;
+        let pot = cycle_pot(cycle, &leaderboard)?;
+
+        for vote in player_votes {
+            votes.push(my_vote(vote, &totals, pot)?);
+        }
+    }
+
+    let message = MyVotesMessage {
+        _type: "my_votes",
+        address,
+        cycle_id: cycle.map(|cycle| cycle.id.to_string()),
+        votes,
+    };
+    serde_json::to_string(&message).map_err(|e| e.to_string())
+}
+
+/// Ranks a vote against the current symbol totals, which are ordered winner first
+fn my_vote(vote: Vote, totals: &[(Symbol, U256)], pot: TokenAmount) -> Result<MyVote, String> {
+    let symbol = Symbol::from(vote.symbol);
+    let amount = uint256(&vote.amount)?;
+    let rank = totals
+        .iter()
+        .position(|(total_symbol, _)| *total_symbol == symbol)
+        .map_or(totals.len() + 1, |position| position + 1);
+
+    // the leading symbol's votes split the pot in proportion to their amount
+    let projected_reward = match totals.first() {
+        Some((leader, leader_amount)) if *leader == symbol && !leader_amount.is_zero() => pot
+            .wei()
+            .checked_mul(amount)
+            .map(|share| TokenAmount::from_wei(share / leader_amount))
+            .ok_or_else(|| format!("projected reward of vote {} overflowed", vote.id))?,
+        _ => TokenAmount::ZERO,
+    };
+
+    Ok(MyVote {
+        id: vote.id.to_string(),
+        emoji: symbol.to_string(),
+        amount: amount.to_string(),
+        rank,
+        projected_reward,
+    })
+}
+
+/// Builds the `my_claims` message for `address`: their winning votes and whether each has been
+/// claimed
+pub async fn my_claims_message(
+    database: &Database,
+    chain_id: &BigDecimal,
+    address: Address,
+) -> Result<String, String> {
+    let claims = database
+        .get_player_claims(address, chain_id.clone(), MAX_CLAIMS)
+        .await
+        .map_err(|e| format!("could not fetch claims of {}: {}", address, e))?;
+
+    let message = MyClaimsMessage {
+        _type: "my_claims",
+        address,
+        claims: claims.into_iter().map(my_claim).collect::<Result<_, _>>()?,
+    };
+    serde_json::to_string(&message).map_err(|e| e.to_string())
+}
+
+fn my_claim(claim: PlayerClaim) -> Result<MyClaim, String> {
+    let reward = claim
+        .reward
+        .as_ref()
+        .map(TokenAmount::try_from_bigdecimal)
+        .transpose()
+        .map_err(|e| format!("invalid reward for vote {}: {}", claim.vote_id, e))?;
+
+    Ok(MyClaim {
+        vote_id: claim.vote_id.to_string(),
+        cycle_id: claim.cycle_id.to_string(),
+        emoji: Symbol::from(claim.symbol).to_string(),
+        amount: uint256(&claim.amount)?.to_string(),
+        status: if claim.claimed {
+            ClaimStatus::Claimed
+        } else {
+            ClaimStatus::Claimable
+        },
+        reward,
+        claimed_block: claim.claimed_block.map(|block| block.to_string()),
+    })
+}
+
+fn uint256(value: &BigDecimal) -> Result<U256, String> {
+    U256::try_from_bigdecimal(value).map_err(|e| format!("invalid uint256 {}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use database::testing::{self, setup_db, PLACER};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const PLAYER: &str = PLACER;
+    const RIVAL: &str = "0x00000000000000000000000000000000000000bb";
+
+    fn vote(id: u64, placer: &str, emoji: &str, amount: u64) -> Vote {
+        let symbol = emoji.as_bytes().try_into().unwrap();
+        Vote {
+            placer: placer.parse().unwrap(),
+            placement: Some(BigDecimal::from(id)),
+            ..testing::vote(id, 1, symbol, amount, 100 + id)
+        }
+    }
+
+    #[test]
+    fn ranks_votes_and_projects_their_rewards() {
+        let totals = [
+            ("🚀".parse::<Symbol>().unwrap(), U256::from(5)),
+            ("🔥".parse::<Symbol>().unwrap(), U256::from(2)),
+        ];
+        let pot = TokenAmount::from_wei(U256::from(70));
+
+        let leading = my_vote(vote(1, PLAYER, "🚀", 2), &totals, pot).unwrap();
+        assert_eq!(leading.rank, 1);
+        // 2 of the leader's 5 votes
+        assert_eq!(
+            leading.projected_reward,
+            TokenAmount::from_wei(U256::from(28))
+        );
+
+        let trailing = my_vote(vote(2, PLAYER, "🔥", 2), &totals, pot).unwrap();
+        assert_eq!(trailing.rank, 2);
+        assert_eq!(trailing.projected_reward, TokenAmount::ZERO);
+
+        // symbols without votes rank after every symbol with some
+        let unranked = my_vote(vote(3, PLAYER, "👍", 1), &totals, pot).unwrap();
+        assert_eq!(unranked.rank, 3);
+        assert_eq!(unranked.projected_reward, TokenAmount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn lists_the_winning_votes_of_finalized_cycles() {
+        let database = setup_db().await;
+        let chain_id = BigDecimal::from(1);
+        let player: Address = PLAYER.parse().unwrap();
+
+        let mut tx = database.start_transaction().await.unwrap();
+        database
+            .create_cycles(&mut tx, vec![testing::cycle(1, 100, 10)])
+            .await
+            .unwrap();
+        database
+            .create_votes(
+                &mut tx,
+                vec![
+                    vote(1, PLAYER, "🚀", 3),
+                    vote(2, RIVAL, "🚀", 1),
+                    vote(3, PLAYER, "🔥", 2),
+                ],
+            )
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // the cycle has not been finalized, so nothing can be claimed yet
+        let message = my_claims_message(&database, &chain_id, player)
+            .await
+            .unwrap();
+        let message: Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(message["claims"], json!([]));
+
+        let mut tx = database.start_transaction().await.unwrap();
+        database
+            .finalize_cycles(&mut tx, BigDecimal::from(200), chain_id.clone(), None)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let message = my_claims_message(&database, &chain_id, player)
+            .await
+            .unwrap();
+        let message: Value = serde_json::from_str(&message).unwrap();
+        // the 🔥 vote lost, and the winning votes split the pot of 6 votes at 10 wei
+        assert_eq!(
+            message["claims"],
+            json!([{
+                "vote_id": "1",
+                "cycle_id": "1",
+                "emoji": "🚀",
+                "amount": "3",
+                "status": "claimable",
+                "reward": "45",
+                "claimed_block": null,
+            }])
+        );
+
+        // without a current cycle there are no votes to rank
+        let message = my_votes_message(&database, &chain_id, player)
+            .await
+            .unwrap();
+        let message: Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(message["cycle_id"], Value::Null);
+        assert_eq!(message["votes"], json!([]));
+    }
+}
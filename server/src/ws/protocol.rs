@@ -0,0 +1,278 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::auth::AuthError;
+
+/// The protocol version this server speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A client message, as in `{"v": 1, "id": "1", "op": "subscribe", "payload": {...}}`
+#[derive(Debug)]
+pub struct Envelope {
+    // echoed in the response so the client can correlate it, any JSON value
+    pub id: Option<Value>,
+    pub op: Op,
+    pub payload: Value,
+    // sent without an `op`, as clients did before the envelope existed
+    pub legacy: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Subscribe,
+    Unsubscribe,
+    Ping,
+    Query,
+}
+
+impl Op {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "subscribe" => Some(Self::Subscribe),
+            "unsubscribe" => Some(Self::Unsubscribe),
+            "ping" => Some(Self::Ping),
+            "query" => Some(Self::Query),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    #[serde(default = "current_version")]
+    v: u32,
+    id: Option<Value>,
+    op: String,
+    #[serde(default)]
+    payload: Value,
+}
+
+fn current_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+impl Envelope {
+    /// Parses a client message
+    ///
+    /// Messages without an `op` are the unversioned requests clients sent before the envelope
+    /// existed, and are read as the payload of a `subscribe` that replaces every subscription.
+    pub fn parse(text: &str) -> Result<Self, ProtocolError> {
+        let message: Map<String, Value> = serde_json::from_str(text)
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidMessage, e.to_string()))?;
+        if !message.contains_key("op") {
+            return Ok(Self {
+                id: None,
+                op: Op::Subscribe,
+                payload: Value::Object(message),
+                legacy: true,
+            });
+        }
+
+        let raw: RawEnvelope = serde_json::from_value(Value::Object(message))
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidMessage, e.to_string()))?;
+        let error = |code, message| ProtocolError {
+            id: raw.id.clone(),
+            code,
+            message,
+        };
+        if raw.v != PROTOCOL_VERSION {
+            return Err(error(
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "version {} is not supported, use {}",
+                    raw.v, PROTOCOL_VERSION
+                ),
+            ));
+        }
+        let Some(op) = Op::from_name(&raw.op) else {
+            return Err(error(
+                ErrorCode::UnknownOp,
+                format!("unknown op `{}`", raw.op),
+            ));
+        };
+
+        Ok(Self {
+            id: raw.id,
+            op,
+            payload: raw.payload,
+            legacy: false,
+        })
+    }
+
+    /// Reads the payload as the op's request type
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, ProtocolError> {
+        // a missing payload reads like an empty object
+        let payload = match &self.payload {
+            Value::Null => Value::Object(Map::new()),
+            payload => payload.clone(),
+        };
+        serde_json::from_value(payload)
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidPayload, e.to_string()))
+    }
+}
+
+/// Stable error codes, so clients can handle failures without matching on messages
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The message is not a JSON object or not a valid envelope
+    InvalidMessage,
+    /// The envelope's `v` is not [`PROTOCOL_VERSION`]
+    UnsupportedVersion,
+    /// The envelope's `op` is not one of the known operations
+    UnknownOp,
+    /// The payload does not fit the op
+    InvalidPayload,
+    /// The sign-in or session token was rejected
+    Unauthorized,
+    /// A player subscription was requested by a socket that has not signed in, or stopped
+    /// without an address
+    AddressRequired,
+    /// The request's address is not the one the socket signed in as
+    AddressMismatch,
+    /// The server is already publishing as many leaderboard topics as it can, or the socket
+    /// watches as many as it may
+    TooManyTopics,
+    /// The leaderboard's chain or cycle has not been indexed
+    UnknownTopic,
+    /// The socket already has as many queries waiting for an answer as it may
+    TooManyQueries,
+    /// The requested data could not be fetched, the request may be retried
+    Unavailable,
+}
+
+/// A rejected client message, sent back as `{"type": "error", "id", "code", "message"}`
+#[derive(Debug, Serialize)]
+pub struct ProtocolError {
+    // the id of the rejected message, when it could be read
+    pub id: Option<Value>,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Correlates the error with the message it rejects
+    pub fn with_id(mut self, id: Option<Value>) -> Self {
+        if self.id.is_none() {
+            self.id = id;
+        }
+        self
+    }
+
+    pub fn to_message(&self) -> String {
+        #[derive(Serialize)]
+        struct ErrorMessage<'a> {
+            #[serde(rename = "type")]
+            _type: &'static str,
+            #[serde(flatten)]
+            error: &'a ProtocolError,
+        }
+
+        serde_json::to_string(&ErrorMessage {
+            _type: "error",
+            error: self,
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<AuthError> for ProtocolError {
+    fn from(error: AuthError) -> Self {
+        Self::new(ErrorCode::Unauthorized, error.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct Ack<'a> {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    id: &'a Option<Value>,
+    op: Op,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<Value>,
+}
+
+/// Acknowledges a message that was handled, with the op's result if it has one
+pub fn ack_message(id: &Option<Value>, op: Op, payload: Option<Value>) -> String {
+    serde_json::to_string(&Ack {
+        _type: "ack",
+        id,
+        op,
+        payload,
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::ws::message::{QueryRequest, SubscriptionType};
+
+    #[test]
+    fn parses_envelopes() {
+        let envelope =
+            Envelope::parse(r#"{"v": 1, "id": 7, "op": "query", "payload": {"type": "online"}}"#)
+                .unwrap();
+        assert_eq!(envelope.id, Some(json!(7)));
+        assert_eq!(envelope.op, Op::Query);
+
+        // payloads are buffered, so their fields cannot borrow from the message
+        let query = Envelope::parse(
+            r#"{"op": "query", "payload": {"type": "my_votes", "address": "0x0000000000000000000000000000000000000001"}}"#,
+        )
+        .unwrap();
+        let request: QueryRequest = query.payload().unwrap();
+        assert_eq!(request.subscription, SubscriptionType::MyVotes);
+        assert!(request.address.is_some());
+
+        // unversioned requests are subscriptions
+        let legacy = Envelope::parse(r#"{"subscriptions": ["online"]}"#).unwrap();
+        assert_eq!(legacy.op, Op::Subscribe);
+        assert!(legacy.legacy);
+        assert_eq!(legacy.payload, json!({ "subscriptions": ["online"] }));
+    }
+
+    #[test]
+    fn rejects_invalid_envelopes() {
+        let code = |text: &str| Envelope::parse(text).unwrap_err().code;
+        assert_eq!(code("not json"), ErrorCode::InvalidMessage);
+        assert_eq!(code(r#"["subscribe"]"#), ErrorCode::InvalidMessage);
+        assert_eq!(code(r#"{"op": 1}"#), ErrorCode::InvalidMessage);
+        assert_eq!(
+            code(r#"{"v": 2, "op": "ping"}"#),
+            ErrorCode::UnsupportedVersion
+        );
+        assert_eq!(code(r#"{"op": "publish"}"#), ErrorCode::UnknownOp);
+
+        let error = Envelope::parse(r#"{"id": "a", "op": "publish"}"#).unwrap_err();
+        assert_eq!(
+            serde_json::from_str::<Value>(&error.to_message()).unwrap(),
+            json!({
+                "type": "error",
+                "id": "a",
+                "code": "unknown_op",
+                "message": "unknown op `publish`",
+            })
+        );
+    }
+}
@@ -1,40 +1,54 @@
 use std::error::Error;
 use tokio::time::timeout;
 use std::panic;
-use std::str;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 use std::time::Duration;
 
 use bigdecimal::BigDecimal;
 use bigdecimal::ToPrimitive;
-use database::Database;
+use database::{Cycle, Database, DatabaseError};
 use ethers::providers::{Http, Middleware, Provider};
-use serde::{Deserialize, Serialize};
+use ethers::types::U64;
 use serde_json::json;
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast;
 use tokio::task::JoinSet;
-use bytes::bytes_to_bigdecimal;
+use bytes::{bytes_to_bigdecimal, Symbol, TokenAmount, TryFromBigDecimal};
 use tokio::time;
 
+use super::fanout::{run_elected_publisher, run_replica, FanOut};
+use super::leaderboard::{cycle_pot, Emoji, LeaderboardMessage, Metadata};
+use super::symbols::{SymbolCache, SymbolDetails};
+use super::topics::{LeaderboardTopic, LeaderboardTopics};
 use super::PubSubState;
 
 /// Starts all publishers as threaded tasks
-pub async fn run_publishers(state: Arc<PubSubState>, database_url: &str, rpc_url: &str) {
+///
+/// With a shared fan-out backend, leaderboards are computed by whichever instance is elected
+/// and every instance publishes them to its own subscribers.
+pub async fn run_publishers(state: Arc<PubSubState>, database: Database, rpc_url: &str) {
     let mut set = JoinSet::new();
 
     // publish online users
     set.spawn(publish_online(
         state.tx_online.clone(),
-        state.online.clone(),
+        state.online_total.clone(),
     ));
 
-    let leaderboard = Leaderboard::new(state.tx_leaderboard.clone(), database_url, rpc_url)
+    let leaderboard = Leaderboard::new(state.leaderboards.clone(), database.clone(), rpc_url)
         .await
         .unwrap();
-    set.spawn(async move {
-        leaderboard.start(state.leaderboard.clone()).await;
-    });
+    match &state.fanout {
+        FanOut::InProcess => {
+            set.spawn(async move {
+                leaderboard.start().await;
+            });
+        }
+        FanOut::Postgres { instance_id } => {
+            set.spawn(run_elected_publisher(leaderboard, database.clone()));
+            set.spawn(run_replica(state.clone(), database, instance_id.clone()));
+        }
+    }
 
     // wait for all tasks to complete
     while let Some(res) = set.join_next().await {
@@ -68,174 +82,196 @@ async fn publish_online(sender: broadcast::Sender<String>, online_count: Arc<Ato
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct LeaderboardMessage {
-    #[serde(rename = "type")]
-    _type: String,
-    cycle_id: i64,
-    metadata: Metadata,
-    leaderboard: Vec<Emoji>,
-}
-
-struct Leaderboard {
-    sender: broadcast::Sender<String>,
+pub struct Leaderboard {
+    topics: Arc<LeaderboardTopics>,
     database: Database,
     eth_client: ethers::providers::Provider<Http>,
     chain_id: BigDecimal,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Emoji {
-    emoji: String,
-    value: u32,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Metadata {
-    blocks_remaining: u32,
-    votes: i64,
-    payout: String,
+    symbols: SymbolCache,
 }
 
 impl Leaderboard {
     pub async fn new(
-        sender: broadcast::Sender<String>,
-        database_url: &str,
+        topics: Arc<LeaderboardTopics>,
+        database: Database,
         rpc_url: &str,
     ) -> Result<Self, Box<dyn Error>> {
-        let database = Database::new(database_url).await?;
         let eth_client = Provider::<Http>::try_from(rpc_url).expect("Could not connect to RPC");
         let chain_id = bytes_to_bigdecimal(eth_client.get_chainid().await?);
 
         Ok(Self {
-            sender,
+            topics,
             database,
             eth_client,
             chain_id,
+            symbols: SymbolCache::default(),
         })
     }
 
-    /// Every new mined block, broadcasts the leaderboard
-    async fn start(&self, last_leaderboard: Arc<Mutex<String>>) {
+    /// Every new mined block, broadcasts the leaderboard of each topic with subscribers
+    async fn start(&self) {
         let mut interval = time::interval(Duration::from_secs(5));
 
         loop {
-            interval.tick().await;
-            if self.sender.receiver_count() < 1 {
-                continue;
+            // new topics are published straight away rather than on the next tick
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.topics.created() => {}
             }
 
-            tracing::trace!("publishing leaderboard");
-            if timeout(
-                Duration::from_secs(5),
-                self.publish_leaderboard(last_leaderboard.clone())
-            ).await.is_err() {
-                tracing::error!("failed to publish leaderboard");
+            for topic in self.topics.unsettled_topics() {
+                tracing::trace!("publishing leaderboard {:?}", topic);
+                // checked first, so the leaderboard published next is the final one
+                let settled = self.is_settled(topic).await;
+                match timeout(Duration::from_secs(5), self.build_leaderboard(topic)).await {
+                    Ok(Some(message)) => {
+                        self.topics.publish(topic, message);
+                        if settled {
+                            self.topics.settle(topic);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(_) => tracing::error!("failed to publish leaderboard {:?}", topic),
+                }
             }
         }
     }
 
-    async fn publish_leaderboard(&self, last_leaderboard: Arc<Mutex<String>>) {
-        let cycle_id = match self.database.get_current_cycle(self.chain_id.clone()).await {
-            Ok(cycle_id) => cycle_id,
+    /// Whether a topic's leaderboard can no longer change, which is once its cycle is finalized
+    ///
+    /// Topics that follow the current cycle move on to the next one, so they never settle.
+    pub async fn is_settled(&self, topic: LeaderboardTopic) -> bool {
+        let Some(cycle_id) = topic.cycle_id else { return false };
+        self.database
+            .get_cycle_result(BigDecimal::from(cycle_id), BigDecimal::from(topic.chain_id))
+            .await
+            .is_ok()
+    }
+
+    /// Computes a topic's leaderboard, or returns `None` when there is no cycle to compute it for
+    /// or it could not be computed
+    pub async fn build_leaderboard(&self, topic: LeaderboardTopic) -> Option<LeaderboardMessage> {
+        let chain_id = BigDecimal::from(topic.chain_id);
+        let cycle = match topic.cycle_id {
+            Some(cycle_id) => {
+                self.database
+                    .get_cycle(BigDecimal::from(cycle_id), chain_id.clone())
+                    .await
+            }
+            None => self.database.get_current_cycle(chain_id.clone()).await,
+        };
+        let cycle = match cycle {
+            Ok(cycle) => cycle,
+            Err(DatabaseError::NotFound) => {
+                tracing::trace!("no cycle to publish a leaderboard for {:?}", topic);
+                return None;
+            }
             Err(error) => {
-                tracing::error!("error fetching current cycle from database {:?}", error);
-                return;
+                tracing::error!("error fetching cycle from database: {}", error);
+                return None;
             }
-        }.id.to_i64().unwrap();
-
-        let metadata = match self.generate_metadata().await {
-            Ok(metadata) => metadata,
+        };
+        let cycle_id = cycle.id.to_i64().unwrap_or(0);
+
+        let totals = match self
+            .database
+            .get_leaderboard(cycle.id.clone(), chain_id.clone())
+            .await
+        {
+            Ok(totals) => totals,
             Err(error) => {
-                tracing::error!(error);
-                return;
+                tracing::error!("could not get leaderboard from the database: {}", error);
+                return None;
             }
         };
 
-        let leaderboard = match self.generate_leaderboard().await {
-            Ok(leaderboard) => leaderboard,
+        let metadata = match self.generate_metadata(&cycle, &chain_id, &totals).await {
+            Ok(metadata) => metadata,
             Err(error) => {
                 tracing::error!(error);
-                return;
+                return None;
             }
         };
 
-        let message = LeaderboardMessage {
-            _type: "leaderboard".to_string(),
+        let leaderboard = self.generate_leaderboard(&totals);
+
+        Some(LeaderboardMessage {
             cycle_id,
+            chain_id: topic.chain_id,
             metadata,
             leaderboard,
-        };
-
-        match serde_json::to_string(&message) {
-            Ok(new_leaderboard) => {
-                let mut leaderboard = last_leaderboard.lock().await;
-                *leaderboard = new_leaderboard;
-
-                if let Err(e) = self.sender.send(leaderboard.clone()) {
-                    tracing::error!("failed to broadcast leaderboard: {}", e);
-                }
-            }
-            Err(e) => {
-                tracing::error!("failed to serialize leaderboard: {}", e);
-            }
-        }
+        })
     }
 
-    async fn generate_metadata(&self) -> Result<Metadata, &str> {
-        let Ok(cycle) = self.database.get_current_cycle(self.chain_id.clone()).await else { 
-            return Err("Could not fetch current cycle")
-        };
-        let Ok(current_block) = self.eth_client.get_block_number().await else {
-            return Err("Could not get current block from ethereum client")
-        };
+    async fn generate_metadata(
+        &self,
+        cycle: &Cycle,
+        chain_id: &BigDecimal,
+        totals: &[database::models::Leaderboard],
+    ) -> Result<Metadata, String> {
+        let current_block = self.current_block(chain_id).await?;
         let blocks_remaining = panic::catch_unwind(|| {
-            (cycle.starting_block + cycle.block_length)
+            (&cycle.starting_block + &cycle.block_length)
                 .to_u32()
                 .unwrap_or(0)
                 - current_block.as_u32()
         })
         .unwrap_or(0);
-        let Ok(votes) = self.database.get_vote_count(cycle.id, self.chain_id.clone()).await else {
-            return Err("Could not fetch vote count from database")
-        };
-        let payout = (votes * cycle.vote_price.to_i64().unwrap_or(0)).to_string();
+        let votes = self
+            .database
+            .get_vote_count(cycle.id.clone(), chain_id.clone())
+            .await
+            .map_err(|e| format!("could not fetch vote count from database: {}", e))?;
+        let vote_price = TokenAmount::try_from_bigdecimal(&cycle.vote_price)
+            .map_err(|e| format!("invalid vote price for cycle {}: {}", cycle.id, e))?;
+        let balance = TokenAmount::try_from_bigdecimal(&cycle.balance)
+            .map_err(|e| format!("invalid balance for cycle {}: {}", cycle.id, e))?;
 
         Ok(Metadata {
             blocks_remaining,
             votes,
-            payout,
+            vote_price,
+            payout: cycle_pot(cycle, totals)?,
+            balance,
         })
     }
 
-    async fn generate_leaderboard(&self) -> Result<Vec<Emoji>, &str> {
-        let Ok(cycle) = self.database.get_current_cycle(self.chain_id.clone()).await else {
-            return Err("Could not get current cycle from the database")
-        };
-        let Ok(leaderboard) = self.database.get_leaderboard(cycle.id, self.chain_id.clone()).await else {
-            return Err("Could not get leaderboard from the database")
-        };
+    /// Reads the chain head from the RPC, or for other chains the last block indexed for them
+    async fn current_block(&self, chain_id: &BigDecimal) -> Result<U64, String> {
+        if *chain_id == self.chain_id {
+            return self
+                .eth_client
+                .get_block_number()
+                .await
+                .map_err(|e| format!("could not get current block from ethereum client: {}", e));
+        }
+
+        let block_height = self
+            .database
+            .get_block_height(chain_id.clone())
+            .await
+            .map_err(|e| format!("could not get block height of chain {}: {}", chain_id, e))?;
+        U64::try_from_bigdecimal(&block_height)
+            .map_err(|e| format!("invalid block height {}: {}", block_height, e))
+    }
 
-        let leaderboard: Vec<Emoji> = leaderboard
+    fn generate_leaderboard(&self, totals: &[database::models::Leaderboard]) -> Vec<Emoji> {
+        totals
             .iter()
-            .map(|emoji| Emoji {
-                emoji: str::from_utf8(&emoji.symbol)
-                    .unwrap_or("?")
-                    .to_owned()
-                    .chars()
-                    .next()
-                    .unwrap_or('?')
-                    .to_string(),
-                value: emoji
-                    .amount
-                    .clone()
-                    .unwrap_or(BigDecimal::default())
-                    .to_u32()
-                    .unwrap_or(0),
+            .map(|emoji| {
+                let symbol = Symbol::from_slice(&emoji.symbol);
+                Emoji::new(
+                    symbol,
+                    emoji
+                        .amount
+                        .clone()
+                        .unwrap_or(BigDecimal::default())
+                        .to_u32()
+                        .unwrap_or(0),
+                    self.symbols.get(symbol),
+                )
             })
-            .collect();
-
-        Ok(leaderboard)
+            .collect()
     }
 }
 
@@ -246,9 +282,12 @@ fn generate_mock_leaderboard() -> Vec<Emoji> {
 
     let leaderboard: Vec<Emoji> = emojis
         .iter()
-        .map(|emoji| Emoji {
-            emoji: emoji.to_string(),
-            value: rand::random::<u32>() % 1000,
+        .filter_map(|emoji| {
+            Some(Emoji::new(
+                emoji.parse().ok()?,
+                rand::random::<u32>() % 1000,
+                emojis::get(emoji).map(SymbolDetails::from),
+            ))
         })
         .collect();
 
@@ -261,6 +300,8 @@ fn generate_mock_metadata() -> Metadata {
     Metadata {
         blocks_remaining: rand::random::<u32>() % 100,
         votes: rand::random::<i64>() % 100,
-        payout: (rand::random::<i64>() % 100000000000).to_string(),
+        vote_price: TokenAmount::from_wei((rand::random::<u64>() % 1000000000).into()),
+        payout: TokenAmount::from_wei((rand::random::<u64>() % 100000000000).into()),
+        balance: TokenAmount::ZERO,
     }
 }
@@ -2,53 +2,212 @@ use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 
 use serde_json::json;
-use tokio::sync::{broadcast::Receiver, mpsc::Sender, Mutex};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::{mpsc::Sender, Notify};
+
+use super::feeds::FeedSubscription;
+use super::lag::LagMonitor;
+use super::message::{resync_message, Subscription};
+use super::topics::TopicSubscription;
 
 /// Subscription for broadcasted online count messages
+///
+/// When the socket falls behind, the counts it missed are skipped for the current one.
 pub async fn subscribe_online(
     mut receiver: Receiver<String>,
     sender: Sender<String>,
     online_count: Arc<AtomicU32>,
+    lag: Arc<LagMonitor>,
 ) {
+    let current = || {
+        json!({
+            "type": "online",
+            "count": online_count.load(std::sync::atomic::Ordering::Relaxed),
+        })
+        .to_string()
+    };
+
     // get initial data
-    let online_count = online_count.load(std::sync::atomic::Ordering::Relaxed);
-    if sender
-        .send(
-            json!({
-                "type": "online",
-                "count": online_count,
-            })
-            .to_string(),
-        )
-        .await
-        .is_err()
-    {
+    if sender.send(current()).await.is_err() {
         return;
     }
 
     // subscribe to the online channel
-    while let Ok(msg) = receiver.recv().await {
+    loop {
+        let msg = match receiver.recv().await {
+            Ok(msg) => msg,
+            Err(RecvError::Lagged(skipped)) => {
+                if !report_lag(&sender, &lag, Subscription::Online, skipped).await {
+                    break;
+                }
+                receiver = receiver.resubscribe();
+                current()
+            }
+            Err(RecvError::Closed) => break,
+        };
         if sender.send(msg).await.is_err() {
             break;
         }
     }
 }
 
-/// Subscription for broadcasted leaderboard messages
+/// Subscription for a leaderboard topic, starting with its snapshot and followed by deltas
+///
+/// `resync` re-sends the snapshot, for clients that noticed a gap in the `seq` of the deltas.
+/// When the socket falls behind, the deltas it missed are skipped for the latest snapshot.
 pub async fn subscribe_leaderboard(
-    mut receiver: Receiver<String>,
+    mut subscription: TopicSubscription,
     sender: Sender<String>,
-    leaderboard: Arc<Mutex<String>>,
+    resync: Arc<Notify>,
+    lag: Arc<LagMonitor>,
 ) {
-    // send initial data
-    if sender.send(leaderboard.lock().await.clone()).await.is_err() {
-        return;
+    // a new topic's first snapshot arrives through the channel instead
+    let mut send_snapshot = true;
+    let mut last_seq = 0;
+
+    loop {
+        if send_snapshot {
+            if let Some(snapshot) = subscription.snapshot() {
+                if sender.send(snapshot.message).await.is_err() {
+                    break;
+                }
+                last_seq = snapshot.seq;
+            }
+            send_snapshot = false;
+        }
+
+        tokio::select! {
+            update = subscription.receiver.recv() => match update {
+                // updates already covered by a snapshot are skipped
+                Ok(update) if update.seq > last_seq => {
+                    if sender.send(update.message).await.is_err() {
+                        break;
+                    }
+                    last_seq = update.seq;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    let topic = Subscription::Leaderboard(subscription.topic());
+                    if !report_lag(&sender, &lag, topic, skipped).await {
+                        break;
+                    }
+                    subscription.receiver = subscription.receiver.resubscribe();
+                    send_snapshot = true;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = resync.notified() => send_snapshot = true,
+        }
     }
+}
 
-    // subscribe to the leaderboard channel
-    while let Ok(msg) = receiver.recv().await {
-        if sender.send(msg).await.is_err() {
+/// Tells the client a subscription fell behind its channel, returning false when the
+/// subscription should end instead because the socket lags persistently or is closed
+async fn report_lag(
+    sender: &Sender<String>,
+    lag: &LagMonitor,
+    subscription: Subscription,
+    skipped: u64,
+) -> bool {
+    tracing::debug!(
+        "{:?} subscription fell behind by {} messages",
+        subscription,
+        skipped
+    );
+    if lag.record() {
+        return false;
+    }
+
+    match resync_message(subscription, skipped) {
+        Ok(message) => sender.send(message).await.is_ok(),
+        Err(e) => {
+            tracing::error!("failed to serialize resync message: {}", e);
+            true
+        }
+    }
+}
+
+/// Subscription for a player feed, starting with its latest message and followed by every change
+///
+/// A socket that falls behind only gets the latest message once it catches up.
+pub async fn subscribe_player(mut subscription: FeedSubscription, sender: Sender<String>) {
+    loop {
+        let message = subscription.receiver.borrow_and_update().clone();
+        if let Some(message) = message {
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+        if subscription.receiver.changed().await.is_err() {
             break;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::ws::testing::leaderboard;
+    use crate::ws::topics::{LeaderboardTopic, LeaderboardTopics, TOPIC_CAPACITY};
+
+    const TOPIC: LeaderboardTopic = LeaderboardTopic {
+        chain_id: 1,
+        cycle_id: None,
+    };
+
+    async fn message(receiver: &mut mpsc::Receiver<String>) -> Value {
+        serde_json::from_str(&receiver.recv().await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resyncs_leaderboards_until_they_lag_persistently() {
+        let topics = Arc::new(LeaderboardTopics::default());
+        let subscription = topics.subscribe(TOPIC).unwrap();
+        // the socket takes a single message at a time
+        let (sender, mut receiver) = mpsc::channel(1);
+        let lag = Arc::new(LagMonitor::default());
+        let task = tokio::spawn(subscribe_leaderboard(
+            subscription,
+            sender,
+            Arc::new(Notify::new()),
+            lag.clone(),
+        ));
+
+        let mut votes = 0;
+        for round in 1..=3 {
+            // more updates than the topic holds are published while the subscription waits
+            for _ in 0..TOPIC_CAPACITY + 10 {
+                votes += 1;
+                topics.publish(TOPIC, leaderboard(4, votes));
+            }
+            if round == 1 {
+                // the subscription starts with the latest snapshot
+                let snapshot = message(&mut receiver).await;
+                assert_eq!(snapshot["type"], "leaderboard");
+                assert_eq!(snapshot["metadata"]["votes"], votes);
+            }
+            if round == 3 {
+                break;
+            }
+
+            let resync = message(&mut receiver).await;
+            assert_eq!(resync["type"], "resync");
+            assert_eq!(resync["subscription"]["type"], "leaderboard");
+            assert_eq!(resync["skipped"], 10);
+
+            let snapshot = message(&mut receiver).await;
+            assert_eq!(snapshot["type"], "leaderboard");
+            assert_eq!(snapshot["seq"], votes);
+            assert_eq!(snapshot["metadata"]["votes"], votes);
+        }
+
+        // the third lag within a minute ends the subscription and closes the socket
+        assert!(receiver.recv().await.is_none());
+        task.await.unwrap();
+        assert!(lag.persistent().now_or_never().is_some());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Symbol;
+use emojis::{Emoji, Group, SkinTone};
+use serde::{Deserialize, Serialize};
+use titlecase::titlecase;
+
+/// Descriptive details of an emoji symbol, so clients can label it without an emoji database
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolDetails {
+    pub name: String,
+    pub shortcodes: Vec<String>,
+    pub group: String,
+    // the emoji without a skin tone, only for emojis that support skin tones
+    pub skin_tone_base: Option<String>,
+}
+
+impl From<&Emoji> for SymbolDetails {
+    fn from(emoji: &Emoji) -> Self {
+        Self {
+            name: titlecase(emoji.name()),
+            shortcodes: emoji.shortcodes().map(str::to_owned).collect(),
+            group: group_name(emoji.group()).to_owned(),
+            skin_tone_base: emoji
+                .skin_tone()
+                .and_then(|_| emoji.with_skin_tone(SkinTone::Default))
+                .map(|base| base.as_str().to_owned()),
+        }
+    }
+}
+
+/// Returns the Unicode CLDR name of an emoji group
+fn group_name(group: Group) -> &'static str {
+    match group {
+        Group::SmileysAndEmotion => "Smileys & Emotion",
+        Group::PeopleAndBody => "People & Body",
+        Group::AnimalsAndNature => "Animals & Nature",
+        Group::FoodAndDrink => "Food & Drink",
+        Group::TravelAndPlaces => "Travel & Places",
+        Group::Activities => "Activities",
+        Group::Objects => "Objects",
+        Group::Symbols => "Symbols",
+        Group::Flags => "Flags",
+    }
+}
+
+/// Remembers the details of every symbol that has been looked up
+///
+/// Only valid emojis are cached, which keeps the cache bounded by the size of the emoji list.
+#[derive(Default)]
+pub struct SymbolCache {
+    details: Mutex<HashMap<Symbol, SymbolDetails>>,
+}
+
+impl SymbolCache {
+    /// Returns the details of a symbol, or `None` when it is not an emoji
+    pub fn get(&self, symbol: Symbol) -> Option<SymbolDetails> {
+        let mut details = self.details.lock().unwrap();
+        if let Some(cached) = details.get(&symbol) {
+            return Some(cached.clone());
+        }
+
+        let emoji = symbol.emoji().ok()?;
+        Some(details.entry(symbol).or_insert(emoji.into()).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(emoji: &str) -> Option<SymbolDetails> {
+        SymbolCache::default().get(emoji.parse().unwrap())
+    }
+
+    #[test]
+    fn describes_emojis() {
+        let fire = details("🔥").unwrap();
+        assert_eq!(fire.name, "Fire");
+        assert_eq!(fire.shortcodes, vec!["fire"]);
+        assert_eq!(fire.group, "Travel & Places");
+        assert_eq!(fire.skin_tone_base, None);
+
+        // four bytes only fit an emoji without a skin tone, which is its own base
+        let thumbs_up = details("👍").unwrap();
+        assert_eq!(thumbs_up.name, "Thumbs Up");
+        assert_eq!(thumbs_up.group, "People & Body");
+        assert_eq!(thumbs_up.skin_tone_base.as_deref(), Some("👍"));
+    }
+
+    #[test]
+    fn caches_only_emojis() {
+        let cache = SymbolCache::default();
+        let fire: Symbol = "🔥".parse().unwrap();
+        assert!(cache.get(fire) == emojis::get("🔥").map(SymbolDetails::from));
+        assert!(cache.details.lock().unwrap().contains_key(&fire));
+
+        // the second lookup is served from the cache
+        cache.details.lock().unwrap().get_mut(&fire).unwrap().name = "Cached".to_owned();
+        assert_eq!(cache.get(fire).unwrap().name, "Cached");
+
+        assert!(cache.get(Symbol::from(*b"ABCD")).is_none());
+        assert_eq!(cache.details.lock().unwrap().len(), 1);
+    }
+}
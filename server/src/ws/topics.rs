@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, Notify};
+
+use super::leaderboard::LeaderboardMessage;
+
+/// Most leaderboard topics published at once, further topics are refused until one is torn down
+const MAX_LEADERBOARD_TOPICS: usize = 100;
+
+/// Most updates a topic holds for subscribers that have not read them yet, subscribers further
+/// behind skip to the topic's snapshot
+pub const TOPIC_CAPACITY: usize = 32;
+
+/// A leaderboard stream, for one cycle or for whichever cycle is current on a chain
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize)]
+pub struct LeaderboardTopic {
+    pub chain_id: u64,
+    // follows the current cycle when `None`
+    pub cycle_id: Option<u64>,
+}
+
+/// Formats a topic as `<chain_id>:<cycle_id>`, or `<chain_id>:current`, to name it outside the
+/// process
+impl fmt::Display for LeaderboardTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cycle_id {
+            Some(cycle_id) => write!(f, "{}:{}", self.chain_id, cycle_id),
+            None => write!(f, "{}:current", self.chain_id),
+        }
+    }
+}
+
+impl FromStr for LeaderboardTopic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid leaderboard topic `{}`", s);
+        let (chain_id, cycle_id) = s.split_once(':').ok_or_else(invalid)?;
+
+        Ok(Self {
+            chain_id: chain_id.parse().map_err(|_| invalid())?,
+            cycle_id: match cycle_id {
+                "current" => None,
+                cycle_id => Some(cycle_id.parse().map_err(|_| invalid())?),
+            },
+        })
+    }
+}
+
+/// A message broadcast to a topic's subscribers, numbered so gaps can be detected
+#[derive(Clone, Debug)]
+pub struct LeaderboardUpdate {
+    pub seq: u64,
+    pub message: String,
+}
+
+struct Published {
+    seq: u64,
+    leaderboard: LeaderboardMessage,
+    // the `leaderboard` message for `leaderboard`, sent to new and resyncing subscribers
+    snapshot: String,
+}
+
+struct Topic {
+    sender: broadcast::Sender<LeaderboardUpdate>,
+    // `None` until the topic's first leaderboard has been published
+    published: Option<Published>,
+    // set once the published leaderboard is final, so it is not computed again
+    settled: bool,
+    subscribers: usize,
+}
+
+/// Broadcast channels for the leaderboard topics that have subscribers
+///
+/// Channels are created on the first subscription to a topic and torn down when the last
+/// subscription is dropped, so the publisher only computes leaderboards someone is watching.
+#[derive(Default)]
+pub struct LeaderboardTopics {
+    topics: Mutex<HashMap<LeaderboardTopic, Topic>>,
+    // wakes the publisher when a topic is created so its first message is not delayed
+    created: Notify,
+}
+
+/// A subscriber's receiver for a topic, which leaves the topic when dropped
+pub struct TopicSubscription {
+    pub receiver: broadcast::Receiver<LeaderboardUpdate>,
+    topic: LeaderboardTopic,
+    topics: Arc<LeaderboardTopics>,
+}
+
+impl LeaderboardTopics {
+    /// Joins a topic, creating its channel if needed, or returns `None` when too many topics
+    /// are already published
+    pub fn subscribe(self: &Arc<Self>, topic: LeaderboardTopic) -> Option<TopicSubscription> {
+        let mut topics = self.topics.lock().unwrap();
+        if !topics.contains_key(&topic) {
+            if topics.len() >= MAX_LEADERBOARD_TOPICS {
+                return None;
+            }
+            tracing::debug!("creating leaderboard topic {:?}", topic);
+            topics.insert(
+                topic,
+                Topic {
+                    sender: broadcast::channel(TOPIC_CAPACITY).0,
+                    published: None,
+                    settled: false,
+                    subscribers: 0,
+                },
+            );
+            self.created.notify_one();
+        }
+
+        let entry = topics.get_mut(&topic)?;
+        entry.subscribers += 1;
+        Some(TopicSubscription {
+            receiver: entry.sender.subscribe(),
+            topic,
+            topics: self.clone(),
+        })
+    }
+
+    /// Lists the topics that currently have subscribers
+    pub fn topics(&self) -> Vec<LeaderboardTopic> {
+        self.topics.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Lists the topics that have subscribers and whose leaderboard may still change
+    pub fn unsettled_topics(&self) -> Vec<LeaderboardTopic> {
+        let topics = self.topics.lock().unwrap();
+        topics
+            .iter()
+            .filter(|(_, entry)| !entry.settled)
+            .map(|(topic, _)| *topic)
+            .collect()
+    }
+
+    /// Marks a topic's published leaderboard as final, for as long as the topic has subscribers
+    pub fn settle(&self, topic: LeaderboardTopic) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(entry) = topics.get_mut(&topic) {
+            entry.settled = true;
+        }
+    }
+
+    /// Broadcasts what changed in a topic's leaderboard since it was last published, or the
+    /// whole leaderboard when there is nothing to compare against
+    ///
+    /// Nothing is sent when the leaderboard is unchanged or the topic's last subscriber left
+    /// in the meantime.
+    pub fn publish(&self, topic: LeaderboardTopic, leaderboard: LeaderboardMessage) {
+        let mut topics = self.topics.lock().unwrap();
+        let Some(entry) = topics.get_mut(&topic) else { return };
+
+        let previous = entry.published.as_ref();
+        if previous.is_some_and(|previous| previous.leaderboard == leaderboard) {
+            return;
+        }
+        let seq = previous.map_or(1, |previous| previous.seq + 1);
+
+        let snapshot = match leaderboard.snapshot(seq) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                tracing::error!("failed to serialize leaderboard: {}", e);
+                return;
+            }
+        };
+        let delta = previous.and_then(|previous| leaderboard.delta(&previous.leaderboard, seq));
+        let message = match delta {
+            Some(Ok(delta)) => delta,
+            Some(Err(e)) => {
+                tracing::error!("failed to serialize leaderboard delta: {}", e);
+                return;
+            }
+            None => snapshot.clone(),
+        };
+
+        entry.published = Some(Published {
+            seq,
+            leaderboard,
+            snapshot,
+        });
+        if let Err(e) = entry.sender.send(LeaderboardUpdate { seq, message }) {
+            tracing::error!("failed to broadcast leaderboard {:?}: {}", topic, e);
+        }
+    }
+
+    /// Waits until a new topic is created
+    pub async fn created(&self) {
+        self.created.notified().await;
+    }
+
+    fn unsubscribe(&self, topic: LeaderboardTopic) {
+        let mut topics = self.topics.lock().unwrap();
+        let Some(entry) = topics.get_mut(&topic) else { return };
+
+        entry.subscribers -= 1;
+        if entry.subscribers == 0 {
+            tracing::debug!("tearing down leaderboard topic {:?}", topic);
+            topics.remove(&topic);
+        }
+    }
+}
+
+impl TopicSubscription {
+    pub fn topic(&self) -> LeaderboardTopic {
+        self.topic
+    }
+
+    /// Returns the topic's latest leaderboard in full, once one has been published
+    pub fn snapshot(&self) -> Option<LeaderboardUpdate> {
+        let topics = self.topics.topics.lock().unwrap();
+        let published = topics.get(&self.topic)?.published.as_ref()?;
+        Some(LeaderboardUpdate {
+            seq: published.seq,
+            message: published.snapshot.clone(),
+        })
+    }
+}
+
+impl Drop for TopicSubscription {
+    fn drop(&mut self) {
+        self.topics.unsubscribe(self.topic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    use super::*;
+    use crate::ws::testing::leaderboard;
+
+    const TOPIC: LeaderboardTopic = LeaderboardTopic {
+        chain_id: 1,
+        cycle_id: Some(4),
+    };
+
+    #[test]
+    fn creates_topics_until_their_last_subscriber_leaves() {
+        let topics = Arc::new(LeaderboardTopics::default());
+        let first = topics.subscribe(TOPIC).unwrap();
+        // the publisher is woken for the new topic
+        assert!(topics.created().now_or_never().is_some());
+
+        let second = topics.subscribe(TOPIC).unwrap();
+        assert!(topics.created().now_or_never().is_none());
+        assert_eq!(topics.topics(), [TOPIC]);
+        assert!(first.snapshot().is_none());
+
+        drop(first);
+        assert_eq!(topics.topics(), [TOPIC]);
+        drop(second);
+        assert!(topics.topics().is_empty());
+
+        // leaderboards of topics torn down in the meantime are dropped
+        topics.publish(TOPIC, leaderboard(4, 1));
+        assert!(topics.subscribe(TOPIC).unwrap().snapshot().is_none());
+    }
+
+    #[test]
+    fn publishes_a_snapshot_then_deltas() {
+        let topics = Arc::new(LeaderboardTopics::default());
+        let mut subscription = topics.subscribe(TOPIC).unwrap();
+
+        topics.publish(TOPIC, leaderboard(4, 1));
+        let update = subscription.receiver.try_recv().unwrap();
+        assert_eq!(update.seq, 1);
+        assert!(update.message.contains(r#""type":"leaderboard""#));
+        assert_eq!(subscription.snapshot().unwrap().message, update.message);
+
+        // unchanged leaderboards are not sent again
+        topics.publish(TOPIC, leaderboard(4, 1));
+        assert_eq!(
+            subscription.receiver.try_recv().unwrap_err(),
+            TryRecvError::Empty
+        );
+
+        topics.publish(TOPIC, leaderboard(4, 2));
+        let update = subscription.receiver.try_recv().unwrap();
+        assert_eq!(update.seq, 2);
+        assert!(update.message.contains(r#""type":"leaderboard_delta""#));
+        let snapshot = subscription.snapshot().unwrap();
+        assert_eq!(snapshot.seq, 2);
+        assert_eq!(snapshot.message, leaderboard(4, 2).snapshot(2).unwrap());
+    }
+
+    #[test]
+    fn refuses_topics_past_the_limit() {
+        let topics = Arc::new(LeaderboardTopics::default());
+        let mut subscriptions: Vec<_> = (0..MAX_LEADERBOARD_TOPICS as u64)
+            .map(|cycle_id| {
+                let topic = LeaderboardTopic {
+                    chain_id: 8453,
+                    cycle_id: Some(cycle_id),
+                };
+                topics.subscribe(topic).unwrap()
+            })
+            .collect();
+
+        assert!(topics.subscribe(TOPIC).is_none());
+        // topics that are already published can still be joined
+        let topic = subscriptions[0].topic();
+        assert!(topics.subscribe(topic).is_some());
+
+        subscriptions.pop();
+        assert!(topics.subscribe(TOPIC).is_some());
+    }
+
+    #[test]
+    fn settles_topics_until_they_are_torn_down() {
+        let topics = Arc::new(LeaderboardTopics::default());
+        let subscription = topics.subscribe(TOPIC).unwrap();
+        assert_eq!(topics.unsettled_topics(), [TOPIC]);
+
+        topics.settle(TOPIC);
+        assert!(topics.unsettled_topics().is_empty());
+        assert_eq!(topics.topics(), [TOPIC]);
+
+        // a topic created again has not been published, so it is computed again
+        drop(subscription);
+        let _subscription = topics.subscribe(TOPIC).unwrap();
+        assert_eq!(topics.unsettled_topics(), [TOPIC]);
+    }
+
+    #[test]
+    fn topics_round_trip_through_strings() {
+        for topic in [
+            LeaderboardTopic {
+                chain_id: 8453,
+                cycle_id: Some(42),
+            },
+            LeaderboardTopic {
+                chain_id: 1,
+                cycle_id: None,
+            },
+        ] {
+            assert_eq!(topic.to_string().parse::<LeaderboardTopic>(), Ok(topic));
+        }
+        assert_eq!(
+            "1:current"
+                .parse::<LeaderboardTopic>()
+                .map(|topic| topic.cycle_id),
+            Ok(None)
+        );
+        assert!("1".parse::<LeaderboardTopic>().is_err());
+        assert!("1:latest".parse::<LeaderboardTopic>().is_err());
+    }
+}
@@ -1,39 +1,145 @@
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::extract::ws::Message;
+use axum::extract::ws::{close_code, CloseFrame, Message};
 use axum::extract::{ws::WebSocket, State};
 use axum::extract::{ConnectInfo, WebSocketUpgrade};
 use axum::response::IntoResponse;
-use futures::{sink::SinkExt, stream::StreamExt};
-use tokio::sync::{broadcast, mpsc, Mutex};
-use tokio::task::JoinSet;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use bytes::Address;
+use chrono::Utc;
+use database::{Database, DatabaseError};
+use futures::sink::SinkExt;
+use futures::stream::{SplitSink, SplitStream, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time;
+use tokio::time::Instant;
 
+use crate::auth::{Auth, Session};
+use crate::ws::fanout::FanOut;
+use crate::ws::feeds::{PlayerFeed, PlayerFeeds};
+use crate::ws::lag::LagMonitor;
 use crate::ws::message::SubscriptionType;
+use crate::ws::players::{my_claims_message, my_votes_message};
+use crate::ws::protocol::{ack_message, Envelope, ErrorCode, Op, ProtocolError};
+use crate::ws::topics::{LeaderboardTopic, LeaderboardTopics};
 
-use super::message::PubSubRequest;
-use super::{process_subscription_message, subscribe_leaderboard, subscribe_online};
+use super::message::{
+    authenticated_message, PubSubRequest, QueryRequest, Subscription, UnsubscribeRequest,
+};
+use super::{subscribe_leaderboard, subscribe_online, subscribe_player};
+
+/// How long a leaderboard query waits for a topic nobody watches to be published
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most leaderboard topics a socket may subscribe to
+const MAX_TOPICS_PER_SOCKET: usize = 10;
+
+/// Most queries a socket may have waiting for an answer, each of which may hold a leaderboard
+/// topic while it waits
+const MAX_PENDING_QUERIES: usize = 2;
+
+/// Most online counts held for sockets that have not read them yet, sockets further behind skip
+/// to the current count
+const ONLINE_CAPACITY: usize = 16;
+
+/// Most messages queued for a socket, subscriptions that would overflow it wait and fall behind
+/// their channels instead
+const OUTBOUND_BUFFER: usize = 256;
+
+/// How the server checks that clients are still there
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    // how often sockets are pinged
+    pub ping_interval: Duration,
+    // how long a client has to answer a ping before its socket is closed
+    pub ping_timeout: Duration,
+    // how long a client may stay connected without subscribing to anything
+    pub idle_timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            ping_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Counts of the sockets closed by the server rather than the client
+#[derive(Default)]
+pub struct ReapedConnections {
+    // clients that stopped answering pings
+    pub unresponsive: AtomicU64,
+    // clients that never subscribed
+    pub idle: AtomicU64,
+    // clients that kept falling behind their subscriptions
+    pub lagging: AtomicU64,
+}
 
 pub struct PubSubState {
     // the count of users connected to ws server
     pub online: Arc<AtomicU32>,
+    // the count sent to clients, which is `online` summed over every server instance when they
+    // share a fan-out backend
+    pub online_total: Arc<AtomicU32>,
     // channel that sends information about online users
     pub tx_online: broadcast::Sender<String>,
-    // the actual leaderboard in text JSON format
-    pub leaderboard: Arc<Mutex<String>>,
-    // channel that sends information about leaderboard
-    pub tx_leaderboard: broadcast::Sender<String>,
+    // channels of the leaderboard topics that have subscribers
+    pub leaderboards: Arc<LeaderboardTopics>,
+    // database that per-player queries read from
+    pub database: Database,
+    // polls shared by the per-player subscriptions
+    pub players: Arc<PlayerFeeds>,
+    // chain the indexed data belongs to
+    pub chain_id: BigDecimal,
+    // verifies sign-ins that bind an address to a socket
+    pub auth: Arc<Auth>,
+    pub heartbeat: Heartbeat,
+    pub reaped: ReapedConnections,
+    // how leaderboards and online counts are shared with other server instances
+    pub fanout: FanOut,
 }
 
-impl Default for PubSubState {
-    fn default() -> Self {
+impl PubSubState {
+    pub fn new(database: Database, chain_id: BigDecimal, auth: Arc<Auth>) -> Self {
+        let online = Arc::new(AtomicU32::new(0));
         Self {
-            online: Arc::new(AtomicU32::new(0)),
-            tx_online: broadcast::channel(10_000).0,
-            leaderboard: Arc::new(Mutex::new("".to_string())),
-            tx_leaderboard: broadcast::channel(10_000).0,
+            online_total: online.clone(),
+            online,
+            tx_online: broadcast::channel(ONLINE_CAPACITY).0,
+            leaderboards: Arc::new(LeaderboardTopics::default()),
+            players: Arc::new(PlayerFeeds::new(database.clone(), chain_id.clone())),
+            database,
+            chain_id,
+            auth,
+            heartbeat: Heartbeat::default(),
+            reaped: ReapedConnections::default(),
+            fanout: FanOut::InProcess,
+        }
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Shares broadcasts with other server instances through `fanout`, which then provides the
+    /// online count sent to clients
+    pub fn with_fanout(mut self, fanout: FanOut) -> Self {
+        if !matches!(fanout, FanOut::InProcess) {
+            self.online_total = Arc::new(AtomicU32::new(0));
         }
+        self.fanout = fanout;
+        self
     }
 }
 
@@ -46,103 +152,976 @@ pub async fn websocket_handler(
     ws.on_upgrade(move |socket| websocket(socket, state, socket_address))
 }
 
+/// Why a socket was closed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Disconnect {
+    // the client closed the socket or it failed
+    Closed,
+    // the client did not answer a ping in time
+    Unresponsive,
+    // the client did not subscribe to anything in time
+    Idle,
+    // the client kept falling behind its subscriptions
+    Lagging,
+}
+
+impl Disconnect {
+    /// The close frame sent to the client, if it is still worth telling
+    fn close_frame(self) -> Option<CloseFrame<'static>> {
+        match self {
+            Self::Closed => None,
+            Self::Unresponsive => Some(CloseFrame {
+                code: close_code::AWAY,
+                reason: "ping timeout".into(),
+            }),
+            Self::Idle => Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "no subscriptions".into(),
+            }),
+            Self::Lagging => Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "too slow".into(),
+            }),
+        }
+    }
+}
+
+/// What the reading half of a socket tells the writing half
+enum Inbound {
+    // a frame was received, so the client is alive
+    Alive,
+    // the socket should be closed by the server
+    Close(Disconnect),
+}
+
 /// Handles websocket connections by processing incoming messages as subscription requests and
 /// sending outgoing messages to subscribed users
+///
+/// Clients are pinged every `ping_interval` and any frame counts as an answer. Sockets that do
+/// not answer within `ping_timeout`, that go `idle_timeout` without a subscription or that keep
+/// falling behind their subscriptions are closed.
 async fn websocket(stream: WebSocket, state: Arc<PubSubState>, socket_address: SocketAddr) {
     // split the stream to allow for simultaneous sending and receiving
-    let (mut sink, mut stream) = stream.split();
+    let (sink, stream) = stream.split();
 
     // create an mpsc so we can send messages to the stream from multiple threads
-    let (sender, mut receiver) = mpsc::channel::<String>(1000);
+    let (sender, receiver) = mpsc::channel::<String>(OUTBOUND_BUFFER);
+    let (inbound_sender, inbound) = mpsc::unbounded_channel();
 
     // add 1 to online count
     state.online.fetch_add(1, Ordering::Relaxed);
 
-    let mut socket_join_set = JoinSet::new();
+    // listens for new messages from the user to update their subscription
+    let lag = Arc::new(LagMonitor::default());
+    let connection = Connection {
+        state: state.clone(),
+        sender,
+        socket_address,
+        subscriptions: Subscriptions::new(lag.clone()),
+        session: None,
+        subscribed: false,
+        queries: JoinSet::new(),
+    };
+    let reader = tokio::spawn(read_socket(stream, connection, inbound_sender));
+
+    // since SplitSinks are not thread safe, a single task writes the messages sent through the
+    // mpsc to the SplitSink, along with pings and close frames
+    let disconnect = write_socket(sink, receiver, inbound, &lag, state.heartbeat).await;
+    reader.abort();
+
+    let reaped = match disconnect {
+        Disconnect::Closed => None,
+        Disconnect::Unresponsive => Some(&state.reaped.unresponsive),
+        Disconnect::Idle => Some(&state.reaped.idle),
+        Disconnect::Lagging => Some(&state.reaped.lagging),
+    };
+    match reaped {
+        Some(count) => {
+            count.fetch_add(1, Ordering::Relaxed);
+            tracing::info!(
+                "reaped {} ({:?}), {} unresponsive, {} idle and {} lagging sockets reaped so far",
+                socket_address,
+                disconnect,
+                state.reaped.unresponsive.load(Ordering::Relaxed),
+                state.reaped.idle.load(Ordering::Relaxed),
+                state.reaped.lagging.load(Ordering::Relaxed)
+            );
+        }
+        None => tracing::trace!("websocket closed for {}", socket_address),
+    }
 
-    // since SplitSinks are not thread safe, we create an mpsc channel that will forward
-    // messages to the SplitSink (`sink`)
-    socket_join_set.spawn(async move {
-        while let Some(message) = receiver.recv().await {
-            if let Err(e) = sink.send(Message::Text(message)).await {
-                tracing::trace!("error sending message to {}: {}", socket_address, e);
-                break;
+    // clean up when connection closes
+    state.online.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Handles the client's messages until it closes the socket, or asks for the socket to be
+/// closed when the client goes without a subscription for too long
+async fn read_socket(
+    mut stream: SplitStream<WebSocket>,
+    mut connection: Connection,
+    inbound: mpsc::UnboundedSender<Inbound>,
+) {
+    let idle_timeout = connection.state.heartbeat.idle_timeout;
+    let idle = time::sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            _ = &mut idle, if !connection.subscribed => {
+                let _ = inbound.send(Inbound::Close(Disconnect::Idle));
+                return;
             }
+        };
+        let Some(Ok(message)) = message else { return };
+        let _ = inbound.send(Inbound::Alive);
+
+        let subscribed = connection.subscribed;
+        let closed = match message {
+            Message::Text(text) => connection.handle(&text).await.is_err(),
+            Message::Close(_) => true,
+            // pings are answered by axum, and pongs only show the client is alive
+            _ => false,
+        };
+        if closed {
+            return;
         }
-    });
+        // a client that stopped every subscription has `idle_timeout` to subscribe again
+        if subscribed && !connection.subscribed {
+            idle.as_mut().reset(Instant::now() + idle_timeout);
+        }
+    }
+}
 
-    // listens for new messages from the user to update their subscription
-    let recv_task_sender = sender.clone();
-    let state_clone = state.clone();
-    socket_join_set.spawn(async move {
-        let mut subscription_join_set = JoinSet::new();
-
-        while let Some(Ok(Message::Text(text))) = stream.next().await {
-            match process_subscription_message(text) {
-                Ok(result) => {
-                    tracing::trace!(
-                        "{}: eth address {:?} subscribed to {:?}",
-                        socket_address,
-                        result.address,
-                        result.subscriptions
-                    );
-
-                    // remove old subscriptions
-                    subscription_join_set.abort_all();
-
-                    // create new subscriptions
-                    subscription_join_set =
-                        create_subscriptions(result, state_clone.clone(), recv_task_sender.clone());
+/// Forwards outgoing messages to the client and pings it, until either side closes the socket
+///
+/// A client that does not take a frame within `ping_timeout` is as unresponsive as one that does
+/// not answer pings.
+async fn write_socket(
+    mut sink: SplitSink<WebSocket, Message>,
+    mut receiver: mpsc::Receiver<String>,
+    mut inbound: mpsc::UnboundedReceiver<Inbound>,
+    lag: &LagMonitor,
+    heartbeat: Heartbeat,
+) -> Disconnect {
+    let mut ping = time::interval_at(
+        Instant::now() + heartbeat.ping_interval,
+        heartbeat.ping_interval,
+    );
+    // set while a ping is unanswered
+    let pong_deadline = time::sleep(Duration::ZERO);
+    tokio::pin!(pong_deadline);
+    let mut awaiting_pong = false;
+
+    let disconnect = loop {
+        tokio::select! {
+            // the reader drops the outgoing channel when it stops, so why it stopped is read first
+            biased;
+            event = inbound.recv() => match event {
+                Some(Inbound::Alive) => awaiting_pong = false,
+                Some(Inbound::Close(disconnect)) => break disconnect,
+                // the client closed the socket, which flushes axum's reply to its close frame
+                None => {
+                    let _ = sink.close().await;
+                    break Disconnect::Closed;
                 }
-                Err(e) => {
-                    tracing::info!(
-                        "error processing subscription message from {}: {}",
-                        socket_address,
-                        e
-                    );
-                    if recv_task_sender.send(e.to_string()).await.is_err() {
-                        break;
-                    }
+            },
+            message = receiver.recv() => {
+                let Some(message) = message else { break Disconnect::Closed };
+                let sent = send_frame(&mut sink, Message::Text(message), heartbeat.ping_timeout);
+                if let Err(disconnect) = sent.await {
+                    break disconnect;
                 }
             }
+            _ = ping.tick() => {
+                let sent = send_frame(&mut sink, Message::Ping(Vec::new()), heartbeat.ping_timeout);
+                if let Err(disconnect) = sent.await {
+                    break disconnect;
+                }
+                if !awaiting_pong {
+                    awaiting_pong = true;
+                    pong_deadline.as_mut().reset(Instant::now() + heartbeat.ping_timeout);
+                }
+            }
+            _ = &mut pong_deadline, if awaiting_pong => break Disconnect::Unresponsive,
+            _ = lag.persistent() => break Disconnect::Lagging,
         }
-    });
+    };
 
-    // blocks until any task finishes
-    socket_join_set.join_next().await;
-    socket_join_set.abort_all();
-    tracing::trace!("websocket closed for {}", socket_address);
+    if let Some(frame) = disconnect.close_frame() {
+        let _ = send_frame(
+            &mut sink,
+            Message::Close(Some(frame)),
+            heartbeat.ping_timeout,
+        )
+        .await;
+    }
+    disconnect
+}
 
-    // clean up when connection closes
-    state.online.fetch_sub(1, Ordering::Relaxed);
+/// Sends a frame, giving up on a client that does not take it within `timeout`
+async fn send_frame(
+    sink: &mut SplitSink<WebSocket, Message>,
+    message: Message,
+    timeout: Duration,
+) -> Result<(), Disconnect> {
+    match time::timeout(timeout, sink.send(message)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(_)) => Err(Disconnect::Closed),
+        Err(_) => Err(Disconnect::Unresponsive),
+    }
 }
 
-fn create_subscriptions(
-    result: PubSubRequest,
+/// A socket's subscriptions and sign-in, updated by the messages the client sends
+struct Connection {
     state: Arc<PubSubState>,
     sender: mpsc::Sender<String>,
-) -> JoinSet<()> {
-    let mut join_set = JoinSet::new();
-
-    result
-        .subscriptions
-        .iter()
-        .for_each(|subscription| match subscription {
-            SubscriptionType::Online => {
-                let receiver = state.tx_online.subscribe();
-                let sender = sender.clone();
-                join_set.spawn(subscribe_online(receiver, sender, state.online.clone()));
+    socket_address: SocketAddr,
+    subscriptions: Subscriptions,
+    // the address this socket has proven it controls
+    session: Option<Session>,
+    // whether the client has a subscription running, which keeps an idle socket open
+    subscribed: bool,
+    // queries waiting for an answer, aborted when the socket closes
+    queries: JoinSet<()>,
+}
+
+impl Connection {
+    /// Handles a client message and answers with an acknowledgement or an error, failing only
+    /// once the socket is closed
+    async fn handle(&mut self, text: &str) -> Result<(), SendError<String>> {
+        self.session = self
+            .session
+            .take()
+            .filter(|session| session.expires_at > Utc::now());
+
+        let envelope = match Envelope::parse(text) {
+            Ok(envelope) => envelope,
+            Err(error) => return self.reject(error).await,
+        };
+        let result = match envelope.op {
+            Op::Subscribe => self.subscribe(&envelope).await,
+            Op::Unsubscribe => self.unsubscribe(&envelope).map(Some),
+            Op::Ping => Ok(None),
+            // answered from its own task, so the socket is still read while the query waits
+            Op::Query => return self.query(envelope).await,
+        };
+
+        match result {
+            Ok(payload) => {
+                self.sender
+                    .send(ack_message(&envelope.id, envelope.op, payload))
+                    .await
+            }
+            Err(error) => self.reject(error.with_id(envelope.id)).await,
+        }
+    }
+
+    async fn reject(&self, error: ProtocolError) -> Result<(), SendError<String>> {
+        reject(&self.sender, self.socket_address, error).await
+    }
+
+    /// Signs the socket in when the request carries credentials, then adds to its
+    /// subscriptions and resyncs leaderboards as requested
+    ///
+    /// Subscriptions that are already running are left alone, so they do not re-send their
+    /// initial data. Legacy requests replace the socket's subscriptions instead.
+    async fn subscribe(&mut self, envelope: &Envelope) -> Result<Option<Value>, ProtocolError> {
+        let mut request: PubSubRequest = envelope.payload()?;
+
+        if let Some(credentials) = request.auth.take() {
+            let session = credentials.authenticate(&self.state.auth)?;
+            tracing::debug!("websocket signed in as {}", session.address);
+            if let Ok(message) = authenticated_message(&session) {
+                let _ = self.sender.send(message).await;
+            }
+            self.session = Some(session);
+        }
+        request.bind_session(self.session.as_ref())?;
+
+        if let Some(subscriptions) = &request.subscriptions {
+            tracing::trace!(
+                "{}: eth address {:?} subscribed to {:?}",
+                self.socket_address,
+                request.address,
+                subscriptions
+            );
+
+            let subscriptions = self.resolve(subscriptions, request.address);
+            for subscription in &subscriptions {
+                if let Subscription::Leaderboard(topic) = subscription {
+                    if !self.subscriptions.running.contains_key(subscription) {
+                        check_topic(&self.state, *topic).await?;
+                    }
+                }
+            }
+            self.subscriptions
+                .add(&subscriptions, envelope.legacy, &self.state, &self.sender)?;
+            self.subscribed = !self.subscriptions.running.is_empty();
+        }
+        self.subscriptions
+            .resync(&self.resolve(&request.resync, request.address));
+
+        Ok(Some(self.subscriptions.active_message()))
+    }
+
+    /// Stops the requested subscriptions, or all of them
+    fn unsubscribe(&mut self, envelope: &Envelope) -> Result<Value, ProtocolError> {
+        let mut request: UnsubscribeRequest = envelope.payload()?;
+        request.bind_session(self.session.as_ref())?;
+
+        match &request.subscriptions {
+            Some(subscriptions) => {
+                let subscriptions = self.resolve(subscriptions, request.address);
+                self.subscriptions
+                    .retain(|subscription| !subscriptions.contains(subscription));
+            }
+            None => self.subscriptions.retain(|_| false),
+        }
+        self.subscribed = !self.subscriptions.running.is_empty();
+
+        Ok(self.subscriptions.active_message())
+    }
+
+    fn resolve(
+        &self,
+        subscriptions: &HashSet<SubscriptionType>,
+        address: Option<Address>,
+    ) -> HashSet<Subscription> {
+        let default_chain_id = self.state.chain_id.to_u64().unwrap_or_default();
+        subscriptions
+            .iter()
+            .filter_map(|subscription| subscription.resolve(default_chain_id, address))
+            .collect()
+    }
+
+    /// Starts answering a query with the message its subscription would start with, without
+    /// subscribing, unless too many queries are already waiting
+    async fn query(&mut self, envelope: Envelope) -> Result<(), SendError<String>> {
+        // drop the queries that have been answered
+        while self.queries.try_join_next().is_some() {}
+
+        let request = if self.queries.len() >= MAX_PENDING_QUERIES {
+            Err(ProtocolError::new(
+                ErrorCode::TooManyQueries,
+                format!("at most {} queries can be pending", MAX_PENDING_QUERIES),
+            ))
+        } else {
+            envelope.payload::<QueryRequest>().and_then(|mut request| {
+                request.bind_session(self.session.as_ref())?;
+                Ok(request)
+            })
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(error) => return self.reject(error.with_id(envelope.id)).await,
+        };
+
+        let state = self.state.clone();
+        let sender = self.sender.clone();
+        let socket_address = self.socket_address;
+        self.queries.spawn(async move {
+            let _ = match answer_query(&state, request).await {
+                Ok(payload) => {
+                    sender
+                        .send(ack_message(&envelope.id, Op::Query, Some(payload)))
+                        .await
+                }
+                Err(error) => reject(&sender, socket_address, error.with_id(envelope.id)).await,
+            };
+        });
+        Ok(())
+    }
+}
+
+async fn reject(
+    sender: &mpsc::Sender<String>,
+    socket_address: SocketAddr,
+    error: ProtocolError,
+) -> Result<(), SendError<String>> {
+    tracing::info!(
+        "error processing message from {}: {}",
+        socket_address,
+        error
+    );
+    sender.send(error.to_message()).await
+}
+
+/// Returns the message a subscription would start with
+async fn answer_query(state: &PubSubState, request: QueryRequest) -> Result<Value, ProtocolError> {
+    let unavailable = |e: String| ProtocolError::new(ErrorCode::Unavailable, e);
+    let default_chain_id = state.chain_id.to_u64().unwrap_or_default();
+    let message = match (&request.subscription, request.address) {
+        (SubscriptionType::Online, _) => json!({
+            "type": "online",
+            "count": state.online_total.load(Ordering::Relaxed),
+        })
+        .to_string(),
+        (SubscriptionType::MyVotes, Some(address)) => {
+            my_votes_message(&state.database, &state.chain_id, address)
+                .await
+                .map_err(unavailable)?
+        }
+        (SubscriptionType::MyClaims, Some(address)) => {
+            my_claims_message(&state.database, &state.chain_id, address)
+                .await
+                .map_err(unavailable)?
+        }
+        (subscription, _) => match subscription.leaderboard_topic(default_chain_id) {
+            Some(topic) => leaderboard_snapshot(state, topic).await?,
+            // player queries without an address are rejected when the session is bound
+            None => {
+                return Err(ProtocolError::new(
+                    ErrorCode::AddressRequired,
+                    "an address is required",
+                ))
+            }
+        },
+    };
+
+    serde_json::from_str(&message).map_err(|e| unavailable(e.to_string()))
+}
+
+/// Checks that a leaderboard topic's chain, and its cycle if it names one, have been indexed, so
+/// topics are not created for leaderboards that will never be published
+async fn check_topic(state: &PubSubState, topic: LeaderboardTopic) -> Result<(), ProtocolError> {
+    let unavailable = |e: DatabaseError| ProtocolError::new(ErrorCode::Unavailable, e.to_string());
+    let chain_id = BigDecimal::from(topic.chain_id);
+
+    let indexed_block = state
+        .database
+        .get_block_height(chain_id.clone())
+        .await
+        .map_err(unavailable)?;
+    if indexed_block == BigDecimal::default() {
+        return Err(ProtocolError::new(
+            ErrorCode::UnknownTopic,
+            format!("chain {} is not indexed", topic.chain_id),
+        ));
+    }
+
+    let Some(cycle_id) = topic.cycle_id else { return Ok(()) };
+    match state
+        .database
+        .get_cycle(BigDecimal::from(cycle_id), chain_id)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(DatabaseError::NotFound) => Err(ProtocolError::new(
+            ErrorCode::UnknownTopic,
+            format!("cycle {} is not indexed", cycle_id),
+        )),
+        Err(e) => Err(unavailable(e)),
+    }
+}
+
+/// Reads a leaderboard topic's latest snapshot, briefly joining the topic to have one published
+/// when nobody is watching it
+async fn leaderboard_snapshot(
+    state: &PubSubState,
+    topic: LeaderboardTopic,
+) -> Result<String, ProtocolError> {
+    check_topic(state, topic).await?;
+    let mut subscription = state
+        .leaderboards
+        .subscribe(topic)
+        .ok_or_else(too_many_topics)?;
+    if let Some(snapshot) = subscription.snapshot() {
+        return Ok(snapshot.message);
+    }
+
+    // without a snapshot yet, the topic's first update is a snapshot
+    match time::timeout(QUERY_TIMEOUT, subscription.receiver.recv()).await {
+        Ok(Ok(update)) => Ok(update.message),
+        _ => Err(ProtocolError::new(
+            ErrorCode::Unavailable,
+            "the leaderboard could not be published",
+        )),
+    }
+}
+
+fn too_many_topics() -> ProtocolError {
+    ProtocolError::new(
+        ErrorCode::TooManyTopics,
+        "too many leaderboards are being watched",
+    )
+}
+
+struct RunningSubscription {
+    task: AbortHandle,
+    // wakes a leaderboard subscription to re-send its topic's snapshot
+    resync: Option<Arc<Notify>>,
+}
+
+/// The running subscription tasks of a socket
+struct Subscriptions {
+    tasks: JoinSet<()>,
+    running: HashMap<Subscription, RunningSubscription>,
+    // shared by the subscriptions, which report when they fall behind
+    lag: Arc<LagMonitor>,
+}
+
+impl Subscriptions {
+    fn new(lag: Arc<LagMonitor>) -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            running: HashMap::new(),
+            lag,
+        }
+    }
+
+    /// Starts the subscriptions that are not running yet, or none of them if one is refused, and
+    /// stops the others when `replace` is set
+    fn add(
+        &mut self,
+        subscriptions: &HashSet<Subscription>,
+        replace: bool,
+        state: &Arc<PubSubState>,
+        sender: &mpsc::Sender<String>,
+    ) -> Result<(), ProtocolError> {
+        // drop the tasks that were aborted or have finished
+        while self.tasks.try_join_next().is_some() {}
+
+        let kept = self
+            .running
+            .keys()
+            .filter(|subscription| !replace || subscriptions.contains(subscription));
+        let topics: HashSet<_> = subscriptions
+            .iter()
+            .chain(kept)
+            .filter(|subscription| matches!(subscription, Subscription::Leaderboard(_)))
+            .collect();
+        if topics.len() > MAX_TOPICS_PER_SOCKET {
+            return Err(ProtocolError::new(
+                ErrorCode::TooManyTopics,
+                format!(
+                    "a socket can watch at most {} leaderboards",
+                    MAX_TOPICS_PER_SOCKET
+                ),
+            ));
+        }
+
+        let mut started = Vec::new();
+        for subscription in subscriptions {
+            if self.running.contains_key(subscription) {
+                continue;
             }
-            SubscriptionType::Leaderboard => {
-                let receiver = state.tx_leaderboard.subscribe();
-                let sender = sender.clone();
-                join_set.spawn(subscribe_leaderboard(
+            match self.start(*subscription, state, sender) {
+                Ok(running) => {
+                    self.running.insert(*subscription, running);
+                    started.push(*subscription);
+                }
+                Err(error) => {
+                    self.retain(|subscription| !started.contains(subscription));
+                    return Err(error);
+                }
+            }
+        }
+
+        if replace {
+            self.retain(|subscription| subscriptions.contains(subscription));
+        }
+        Ok(())
+    }
+
+    fn start(
+        &mut self,
+        subscription: Subscription,
+        state: &Arc<PubSubState>,
+        sender: &mpsc::Sender<String>,
+    ) -> Result<RunningSubscription, ProtocolError> {
+        let sender = sender.clone();
+        let mut resync = None;
+        let task = match subscription {
+            Subscription::Online => {
+                let receiver = state.tx_online.subscribe();
+                self.tasks.spawn(subscribe_online(
                     receiver,
                     sender,
-                    state.leaderboard.clone(),
-                ));
+                    state.online_total.clone(),
+                    self.lag.clone(),
+                ))
+            }
+            Subscription::Leaderboard(topic) => {
+                let topic_subscription = state
+                    .leaderboards
+                    .subscribe(topic)
+                    .ok_or_else(too_many_topics)?;
+                let notify = Arc::new(Notify::new());
+                resync = Some(notify.clone());
+                self.tasks.spawn(subscribe_leaderboard(
+                    topic_subscription,
+                    sender,
+                    notify,
+                    self.lag.clone(),
+                ))
+            }
+            Subscription::MyVotes { address } => self.tasks.spawn(subscribe_player(
+                state.players.subscribe(PlayerFeed::MyVotes(address)),
+                sender,
+            )),
+            Subscription::MyClaims { address } => self.tasks.spawn(subscribe_player(
+                state.players.subscribe(PlayerFeed::MyClaims(address)),
+                sender,
+            )),
+        };
+
+        Ok(RunningSubscription { task, resync })
+    }
+
+    /// Stops the subscriptions `keep` returns false for
+    fn retain(&mut self, mut keep: impl FnMut(&Subscription) -> bool) {
+        self.running.retain(|subscription, running| {
+            let keep = keep(subscription);
+            if !keep {
+                running.task.abort();
             }
+            keep
         });
+    }
+
+    fn resync(&self, subscriptions: &HashSet<Subscription>) {
+        subscriptions
+            .iter()
+            .filter_map(|subscription| self.running.get(subscription)?.resync.as_ref())
+            .for_each(|resync| resync.notify_one());
+    }
+
+    /// Lists the running subscriptions, for acknowledgements
+    fn active_message(&self) -> Value {
+        let mut subscriptions: Vec<_> = self.running.keys().collect();
+        subscriptions.sort();
+        json!({ "subscriptions": subscriptions })
+    }
+}
 
-    join_set
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use axum::Router;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    use super::*;
+    use crate::ws::testing::{self, state, topic};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Serves the websocket endpoint on a local port with `heartbeat`
+    async fn serve(heartbeat: Heartbeat) -> (Arc<PubSubState>, String) {
+        let state = Arc::new(state().await.with_heartbeat(heartbeat));
+
+        let app = Router::new()
+            .route("/ws", get(websocket_handler))
+            .with_state(state.clone());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        let url = format!("ws://{}/ws", server.local_addr());
+        tokio::spawn(server);
+
+        (state, url)
+    }
+
+    async fn connect(url: &str) -> Client {
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    async fn send(client: &mut Client, op: &str, payload: Value) {
+        let message = json!({ "v": 1, "op": op, "payload": payload });
+        client
+            .send(ClientMessage::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Reads frames until the server closes the socket, returning its close code and reason
+    async fn closed(client: &mut Client) -> (CloseCode, String) {
+        loop {
+            match client.next().await {
+                Some(Ok(ClientMessage::Close(Some(frame)))) => {
+                    return (frame.code, frame.reason.into_owned())
+                }
+                Some(Ok(_)) => {}
+                message => panic!("socket ended without a close frame: {:?}", message),
+            }
+        }
+    }
+
+    /// Connects without ever answering pings, which `tokio_tungstenite` clients do as they read
+    async fn connect_silent(url: &str) -> TcpStream {
+        let host = url.trim_start_matches("ws://").trim_end_matches("/ws");
+        let mut stream = TcpStream::connect(host).await.unwrap();
+        let request = format!(
+            "GET /ws HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            host
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+        stream
+    }
+
+    /// Reads the server's frames until it closes the socket, returning its close code and reason
+    async fn closed_silent(stream: &mut TcpStream) -> (u16, String) {
+        loop {
+            let opcode = stream.read_u8().await.unwrap() & 0x0f;
+            let len = match stream.read_u8().await.unwrap() {
+                126 => usize::from(stream.read_u16().await.unwrap()),
+                127 => stream.read_u64().await.unwrap() as usize,
+                len => usize::from(len),
+            };
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).await.unwrap();
+
+            // close frames start with their code
+            if opcode == 0x8 {
+                let reason = String::from_utf8(payload[2..].to_vec()).unwrap();
+                return (u16::from_be_bytes([payload[0], payload[1]]), reason);
+            }
+        }
+    }
+
+    /// Waits for the server to count a socket it closed
+    async fn reaped(count: &AtomicU64) {
+        while count.load(Ordering::Relaxed) == 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn leaderboard(cycle_id: u64) -> Subscription {
+        Subscription::Leaderboard(topic(cycle_id))
+    }
+
+    fn set(subscriptions: impl IntoIterator<Item = Subscription>) -> HashSet<Subscription> {
+        subscriptions.into_iter().collect()
+    }
+
+    fn running(subscriptions: &Subscriptions) -> Vec<Subscription> {
+        let mut running: Vec<_> = subscriptions.running.keys().copied().collect();
+        running.sort();
+        running
+    }
+
+    /// Waits for the tasks of stopped subscriptions to be dropped, which leaves their topics
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_and_stops_subscriptions_incrementally() {
+        let state = Arc::new(state().await);
+        let (sender, mut receiver) = mpsc::channel(OUTBOUND_BUFFER);
+        let mut subscriptions = Subscriptions::new(Arc::new(LagMonitor::default()));
+
+        let requested = set([Subscription::Online, leaderboard(1)]);
+        subscriptions
+            .add(&requested, false, &state, &sender)
+            .unwrap();
+        assert_eq!(
+            running(&subscriptions),
+            [Subscription::Online, leaderboard(1)]
+        );
+        let first_task = subscriptions.running[&leaderboard(1)].task.id();
+
+        // running subscriptions are left alone
+        subscriptions
+            .add(
+                &set([leaderboard(1), leaderboard(2)]),
+                false,
+                &state,
+                &sender,
+            )
+            .unwrap();
+        assert_eq!(
+            running(&subscriptions),
+            [Subscription::Online, leaderboard(1), leaderboard(2)]
+        );
+        assert_eq!(subscriptions.running[&leaderboard(1)].task.id(), first_task);
+
+        subscriptions.retain(|subscription| *subscription != leaderboard(1));
+        settle().await;
+        assert_eq!(
+            running(&subscriptions),
+            [Subscription::Online, leaderboard(2)]
+        );
+        assert_eq!(state.leaderboards.topics(), [topic(2)]);
+
+        // a resync re-sends the topic's snapshot
+        state
+            .leaderboards
+            .publish(topic(2), testing::leaderboard(2, 0));
+        let mut snapshot = None;
+        while snapshot.is_none() {
+            let message = receiver.recv().await.unwrap();
+            if message.contains(r#""type":"leaderboard""#) {
+                snapshot = Some(message);
+            }
+        }
+        subscriptions.resync(&set([leaderboard(2)]));
+        assert_eq!(receiver.recv().await, snapshot);
+    }
+
+    #[tokio::test]
+    async fn starts_none_of_the_subscriptions_when_one_is_refused() {
+        let state = Arc::new(state().await);
+        let (sender, _receiver) = mpsc::channel(OUTBOUND_BUFFER);
+        let mut subscriptions = Subscriptions::new(Arc::new(LagMonitor::default()));
+        subscriptions
+            .add(&set([Subscription::Online]), false, &state, &sender)
+            .unwrap();
+
+        // other sockets watch as many topics as the server publishes
+        let others: Vec<_> = (0..)
+            .map(|cycle_id| LeaderboardTopic {
+                chain_id: 8453,
+                cycle_id: Some(cycle_id),
+            })
+            .map_while(|topic| state.leaderboards.subscribe(topic))
+            .collect();
+        let address = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        let requested = set([Subscription::MyVotes { address }, leaderboard(1)]);
+        let error = subscriptions
+            .add(&requested, false, &state, &sender)
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooManyTopics);
+        assert_eq!(running(&subscriptions), [Subscription::Online]);
+        drop(others);
+
+        // more topics than a single socket may watch
+        let requested = set((1..=MAX_TOPICS_PER_SOCKET as u64 + 1).map(leaderboard));
+        let error = subscriptions
+            .add(&requested, false, &state, &sender)
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooManyTopics);
+        assert_eq!(running(&subscriptions), [Subscription::Online]);
+        settle().await;
+        assert!(state.leaderboards.topics().is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_requests_replace_the_subscriptions() {
+        let state = Arc::new(state().await);
+        let (sender, _receiver) = mpsc::channel(OUTBOUND_BUFFER);
+        let mut subscriptions = Subscriptions::new(Arc::new(LagMonitor::default()));
+
+        let requested = set([Subscription::Online, leaderboard(1)]);
+        subscriptions
+            .add(&requested, true, &state, &sender)
+            .unwrap();
+        subscriptions
+            .add(
+                &set([leaderboard(1), leaderboard(2)]),
+                true,
+                &state,
+                &sender,
+            )
+            .unwrap();
+        assert_eq!(running(&subscriptions), [leaderboard(1), leaderboard(2)]);
+
+        // the replaced topics do not count towards the socket's limit
+        let topics = (3..3 + MAX_TOPICS_PER_SOCKET as u64).map(leaderboard);
+        subscriptions
+            .add(&set(topics.clone()), true, &state, &sender)
+            .unwrap();
+        assert_eq!(running(&subscriptions), topics.collect::<Vec<_>>());
+
+        // a refused request keeps the running subscriptions
+        let before = running(&subscriptions);
+        let requested = set((100..101 + MAX_TOPICS_PER_SOCKET as u64).map(leaderboard));
+        assert!(subscriptions
+            .add(&requested, true, &state, &sender)
+            .is_err());
+        assert_eq!(running(&subscriptions), before);
+    }
+
+    #[tokio::test]
+    async fn closes_sockets_that_stop_answering_pings() {
+        let (state, url) = serve(Heartbeat {
+            ping_interval: Duration::from_millis(50),
+            ping_timeout: Duration::from_millis(200),
+            idle_timeout: Duration::from_secs(60),
+        })
+        .await;
+
+        // reading answers the pings, which keeps the socket open
+        let mut client = connect(&url).await;
+        let mut pings = 0;
+        let answering = time::timeout(Duration::from_millis(500), async {
+            loop {
+                match client.next().await {
+                    Some(Ok(ClientMessage::Ping(_))) => pings += 1,
+                    Some(Ok(_)) => {}
+                    message => panic!("socket closed while answering pings: {:?}", message),
+                }
+            }
+        });
+        assert!(answering.await.is_err());
+        assert!(pings >= 5, "only {} pings", pings);
+        assert_eq!(state.reaped.unresponsive.load(Ordering::Relaxed), 0);
+
+        let mut client = connect_silent(&url).await;
+        assert_eq!(
+            time::timeout(Duration::from_secs(5), closed_silent(&mut client))
+                .await
+                .unwrap(),
+            (close_code::AWAY, "ping timeout".to_string())
+        );
+        reaped(&state.reaped.unresponsive).await;
+        assert_eq!(state.reaped.idle.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn closes_sockets_without_subscriptions() {
+        let idle_timeout = Duration::from_millis(300);
+        let (state, url) = serve(Heartbeat {
+            ping_interval: Duration::from_secs(60),
+            ping_timeout: Duration::from_secs(5),
+            idle_timeout,
+        })
+        .await;
+
+        let mut client = connect(&url).await;
+        assert_eq!(
+            time::timeout(Duration::from_secs(5), closed(&mut client))
+                .await
+                .unwrap(),
+            (CloseCode::Policy, "no subscriptions".to_string())
+        );
+        reaped(&state.reaped.idle).await;
+
+        // subscribing keeps the socket open past the timeout
+        let mut client = connect(&url).await;
+        let subscriptions = json!({ "subscriptions": ["online"] });
+        send(&mut client, "subscribe", subscriptions).await;
+        let open = time::timeout(idle_timeout * 2, closed(&mut client));
+        assert!(open.await.is_err());
+
+        // until the last subscription is stopped, which starts the timeout over
+        let stopped = Instant::now();
+        send(&mut client, "unsubscribe", json!(null)).await;
+        assert_eq!(
+            time::timeout(Duration::from_secs(5), closed(&mut client))
+                .await
+                .unwrap(),
+            (CloseCode::Policy, "no subscriptions".to_string())
+        );
+        assert!(stopped.elapsed() >= idle_timeout);
+        assert_eq!(state.reaped.unresponsive.load(Ordering::Relaxed), 0);
+    }
 }